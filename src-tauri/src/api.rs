@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use tauri::{AppHandle, Emitter};
+
+use crate::auth::get_jwt_from_store;
+use crate::settings::api_base_url;
+
+/// Client for the echo API shared by every network call of the recorder. Requests carry the
+/// stored JWT as a bearer token, and a 401 asks the frontend to log in again.
+#[derive(Clone)]
+pub struct ApiClient {
+    app_handle: AppHandle,
+    client: reqwest::Client,
+}
+
+impl ApiClient {
+    pub fn new(app_handle: AppHandle) -> Self {
+        ApiClient {
+            app_handle,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Start a POST to `path`, e.g. `/devents/create`
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = format!("{}{}", api_base_url(&self.app_handle), path);
+        let request = self.client.request(method, url);
+        match self.token() {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    fn token(&self) -> Option<String> {
+        match get_jwt_from_store(&self.app_handle) {
+            Ok(token) => token,
+            Err(e) => {
+                warn!("Failed to read the JWT from the store: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Send a request built by this client. Error statuses are errors, 401 additionally emits
+    /// `auth_required` so the frontend can send the user through login again.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request.send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            warn!("API request was unauthorized: {}", response.url());
+            if let Err(e) = self.app_handle.emit("auth_required", ()) {
+                warn!("Failed to emit auth_required: {:?}", e);
+            }
+            return Err(anyhow!("Not logged in or the session expired"));
+        }
+        Ok(response.error_for_status()?)
+    }
+
+    /// Upload to a presigned storage URL. The URL carries its own authorization, so the JWT is
+    /// not sent along.
+    pub async fn put_presigned(&self, url: &str, content_type: &str, body: Vec<u8>) -> Result<()> {
+        self.client
+            .put(url)
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -1,22 +1,68 @@
-use serde_json::json;
+use anyhow::{anyhow, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 use tauri::AppHandle;
+use tauri::Emitter;
 use tauri::Manager;
 use tauri::Wry;
+use tauri_plugin_shell::ShellExt;
 use tauri_plugin_store::{with_store, StoreCollection};
 use url::Url;
 
-pub fn get_jwt_from_store(app: &AppHandle) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let stores = app.state::<StoreCollection<Wry>>();
-    let path: PathBuf = PathBuf::from("store.bin");
+use crate::api::ApiClient;
+use crate::credentials::Credentials;
+use crate::settings::{api_base_url, auth_paths};
 
-    with_store(app.clone(), stores, path, |store| {
-        let jwt = store
-            .get("jwt_token")
-            .and_then(|v| v.as_str().map(String::from));
-        Ok(jwt)
-    })
-    .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+/// Where the login page sends the browser once the user is logged in, see `handle_deep_link`
+const AUTH_CALLBACK_URL: &str = "invisibility://i.inc/auth_callback";
+/// Tokens are refreshed once they expire within this many seconds
+const REFRESH_MARGIN_SECS: i64 = 10 * 60;
+/// Tokens expiring within this many seconds don't count as valid, so a request doesn't race
+/// the expiry
+const EXPIRY_LEEWAY_SECS: i64 = 30;
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The logged in user, from the claims of the stored JWT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurrentUser {
+    pub id: String,
+    pub email: Option<String>,
+    /// Expiry of the token, unix seconds
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    email: Option<String>,
+    /// Expiry, unix seconds
+    exp: i64,
+}
+
+impl Claims {
+    /// Seconds until the token expires, negative once it has
+    fn expires_in(&self) -> i64 {
+        self.exp - Utc::now().timestamp()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.expires_in() > EXPIRY_LEEWAY_SECS
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RefreshResponse {
+    token: String,
+}
+
+pub fn get_jwt_from_store(app: &AppHandle) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    Ok(app.state::<Credentials>().0.get()?)
 }
 
 pub fn parse_jwt_from_url(url: &str) -> Option<String> {
@@ -32,13 +78,266 @@ pub fn parse_jwt_from_url(url: &str) -> Option<String> {
 }
 
 pub fn save_jwt_to_store(app: &AppHandle, jwt: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(app.state::<Credentials>().0.set(jwt)?)
+}
+
+pub fn clear_jwt_from_store(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(app.state::<Credentials>().0.delete()?)
+}
+
+/// Move a token that older versions kept in plaintext in `store.bin` to the credential store.
+/// A token already in the credential store is newer and wins.
+pub fn migrate_plaintext_jwt(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
     let stores = app.state::<StoreCollection<Wry>>();
     let path: PathBuf = PathBuf::from("store.bin");
 
     with_store(app.clone(), stores, path, |store| {
-        store.insert("jwt_token".to_string(), json!(jwt))?;
+        let Some(jwt) = store
+            .get("jwt_token")
+            .and_then(|v| v.as_str().map(String::from))
+        else {
+            return Ok(());
+        };
+        match get_jwt_from_store(app) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                if let Err(e) = save_jwt_to_store(app, &jwt) {
+                    // Keep the plaintext token rather than logging the user out
+                    error!("Failed to migrate the JWT: {}", e);
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                error!("Failed to read the JWT before migrating: {}", e);
+                return Ok(());
+            }
+        }
+        store.delete("jwt_token")?;
         store.save()?;
+        info!("Moved the JWT out of store.bin");
         Ok(())
     })
     .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
 }
+
+/// Decode the claims of a JWT. The signature is not checked, that is up to the server.
+fn decode_claims(jwt: &str) -> Option<Claims> {
+    let payload = jwt.split('.').nth(1)?;
+    let json = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// The stored JWT with its claims, if there is one that can be decoded
+fn stored_jwt(app: &AppHandle) -> Option<(String, Claims)> {
+    let jwt = match get_jwt_from_store(app) {
+        Ok(jwt) => jwt?,
+        Err(e) => {
+            error!("Failed to read the JWT from the store: {}", e);
+            return None;
+        }
+    };
+    match decode_claims(&jwt) {
+        Some(claims) => Some((jwt, claims)),
+        None => {
+            warn!("Stored JWT is malformed");
+            None
+        }
+    }
+}
+
+/// The stored JWT, unless it is missing, malformed or about to expire
+pub fn valid_jwt(app: &AppHandle) -> Option<String> {
+    stored_jwt(app)
+        .filter(|(_, claims)| claims.is_valid())
+        .map(|(jwt, _)| jwt)
+}
+
+fn current_user_from_store(app: &AppHandle) -> Option<CurrentUser> {
+    let (_, claims) = stored_jwt(app).filter(|(_, claims)| claims.is_valid())?;
+    Some(CurrentUser {
+        id: claims.sub,
+        email: claims.email,
+        expires_at: claims.exp,
+    })
+}
+
+/// Tell the frontend who is logged in now
+fn emit_auth_changed(app: &AppHandle) {
+    if let Err(e) = app.emit("auth_changed", current_user_from_store(app)) {
+        warn!("Failed to emit auth_changed: {:?}", e);
+    }
+}
+
+/// Handle a URL the app was opened with, storing the token of an auth callback
+pub fn handle_deep_link(app: &AppHandle, url: &str) {
+    let Some(jwt) = parse_jwt_from_url(url) else {
+        warn!("Ignoring unexpected deep link {}", url);
+        return;
+    };
+    match decode_claims(&jwt) {
+        Some(claims) if claims.is_valid() => {}
+        Some(_) => {
+            warn!("Ignoring auth callback with an expired token");
+            return;
+        }
+        None => {
+            warn!("Ignoring auth callback with a malformed token");
+            return;
+        }
+    }
+    if let Err(e) = save_jwt_to_store(app, &jwt) {
+        error!("Failed to save the JWT: {}", e);
+        return;
+    }
+    info!("Logged in");
+    emit_auth_changed(app);
+}
+
+/// Exchange the stored token for a fresh one before it expires. Once a token expired without
+/// being refreshed, `auth_changed` and `auth_required` send the user through login again.
+pub fn spawn_token_refresh(app: AppHandle) {
+    let api = ApiClient::new(app.clone());
+    thread::spawn(move || {
+        let mut was_valid = false;
+        loop {
+            let claims = stored_jwt(&app).map(|(_, claims)| claims);
+            let is_valid = claims.as_ref().is_some_and(Claims::is_valid);
+            if was_valid && !is_valid {
+                warn!("JWT expired");
+                emit_auth_changed(&app);
+                if let Err(e) = app.emit("auth_required", ()) {
+                    warn!("Failed to emit auth_required: {:?}", e);
+                }
+            }
+            was_valid = is_valid;
+
+            if is_valid && claims.is_some_and(|claims| claims.expires_in() < REFRESH_MARGIN_SECS) {
+                match refresh_jwt(&api, &auth_paths(&app).refresh) {
+                    Ok(jwt) => match save_jwt_to_store(&app, &jwt) {
+                        Ok(()) => {
+                            info!("Refreshed JWT");
+                            emit_auth_changed(&app);
+                        }
+                        Err(e) => error!("Failed to save the refreshed JWT: {}", e),
+                    },
+                    // Retried on the next check until the token expires
+                    Err(e) => warn!("Failed to refresh JWT: {:?}", e),
+                }
+            }
+            thread::sleep(REFRESH_CHECK_INTERVAL);
+        }
+    });
+}
+
+/// Ask the server for a new token in exchange for the current one, at `path`
+fn refresh_jwt(api: &ApiClient, path: &str) -> anyhow::Result<String> {
+    let response: RefreshResponse = tauri::async_runtime::block_on(async {
+        api.send(api.post(path))
+            .await?
+            .json()
+            .await
+            .context("Invalid refresh response")
+    })?;
+    match decode_claims(&response.token) {
+        Some(claims) if claims.is_valid() => Ok(response.token),
+        _ => Err(anyhow!("Refresh returned an invalid token")),
+    }
+}
+
+/// Open the login page in the browser, which calls back through the `invisibility://` deep link
+#[tauri::command]
+pub fn login(app: AppHandle) -> Result<(), String> {
+    let login_path = auth_paths(&app).login;
+    let mut login_url =
+        Url::parse(&format!("{}{}", api_base_url(&app), login_path)).map_err(|e| e.to_string())?;
+    login_url
+        .query_pairs_mut()
+        .append_pair("redirect_uri", AUTH_CALLBACK_URL);
+    app.shell()
+        .open(login_url.as_str(), None)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn logout(app: AppHandle) -> Result<(), String> {
+    clear_jwt_from_store(&app).map_err(|e| e.to_string())?;
+    info!("Logged out");
+    emit_auth_changed(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn current_user(app: AppHandle) -> Option<CurrentUser> {
+    current_user_from_store(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+
+    fn jwt_with_payload(payload: &str) -> String {
+        format!("eyJhbGciOiJIUzI1NiJ9.{}.c2lnbmF0dXJl", payload)
+    }
+
+    fn jwt_expiring_in(secs: i64) -> String {
+        let claims = serde_json::json!({
+            "sub": "user-1",
+            "email": "ada@example.com",
+            "exp": Utc::now().timestamp() + secs,
+        });
+        jwt_with_payload(&URL_SAFE_NO_PAD.encode(claims.to_string()))
+    }
+
+    #[test]
+    fn decodes_valid_token() {
+        let claims = decode_claims(&jwt_expiring_in(3600)).unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.email.as_deref(), Some("ada@example.com"));
+        assert!(claims.is_valid());
+    }
+
+    #[test]
+    fn expired_tokens_are_not_valid() {
+        let expired = decode_claims(&jwt_expiring_in(-60)).unwrap();
+        assert!(expired.expires_in() < 0);
+        assert!(!expired.is_valid());
+        // About to expire counts as expired
+        let expiring = decode_claims(&jwt_expiring_in(EXPIRY_LEEWAY_SECS - 5)).unwrap();
+        assert!(!expiring.is_valid());
+    }
+
+    #[test]
+    fn malformed_tokens() {
+        let no_email = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-1","exp":4102444800}"#);
+        assert!(decode_claims(&jwt_with_payload(&no_email)).is_some());
+
+        let missing_exp = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-1"}"#);
+        let not_json = URL_SAFE_NO_PAD.encode("not json");
+        for jwt in [
+            String::new(),
+            "no-dots".to_string(),
+            jwt_with_payload(""),
+            jwt_with_payload(&missing_exp),
+            jwt_with_payload(&not_json),
+        ] {
+            assert!(decode_claims(&jwt).is_none(), "{:?}", jwt);
+        }
+    }
+
+    #[test]
+    fn payload_must_be_base64url() {
+        // Standard base64 of this payload has `+` and `/`, which base64url spells `-` and `_`
+        let claims = r#"{"sub":"user-1","email":"a>>>b???c@example.com","exp":4102444800}"#;
+        let standard = STANDARD.encode(claims);
+        assert!(standard.contains('+') && standard.contains('/'));
+        assert!(decode_claims(&jwt_with_payload(&standard)).is_none());
+        assert!(decode_claims(&jwt_with_payload("not*base64")).is_none());
+
+        // Padding is tolerated
+        let padded = URL_SAFE.encode(claims);
+        assert!(padded.ends_with('='));
+        assert!(decode_claims(&jwt_with_payload(&padded)).is_some());
+        assert!(decode_claims(&jwt_with_payload(&URL_SAFE_NO_PAD.encode(claims))).is_some());
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use rand::Rng;
+
+use crate::files::write_atomic;
+
+/// Service the token is filed under in the OS secret store
+const SERVICE: &str = "inc.i.ghost";
+const ACCOUNT: &str = "jwt_token";
+/// Set to `file` to keep the token out of the OS secret store, e.g. on headless machines and in
+/// tests
+pub const BACKEND_ENV: &str = "GHOST_CREDENTIAL_STORE";
+
+const CREDENTIALS_FILE: &str = "credentials.bin";
+const KEY_FILE: &str = "credentials.key";
+const NONCE_LEN: usize = 12;
+
+/// Where the JWT is kept between runs
+pub trait CredentialStore: Send + Sync {
+    fn get(&self) -> Result<Option<String>>;
+    fn set(&self, secret: &str) -> Result<()>;
+    /// Remove the secret, a missing secret is not an error
+    fn delete(&self) -> Result<()>;
+    /// Name for the logs
+    fn name(&self) -> &'static str;
+}
+
+/// Managed state holding the credential store picked at startup
+pub struct Credentials(pub Box<dyn CredentialStore>);
+
+/// Use the Secret Service on Linux, the Keychain on macOS and the Credential Manager on
+/// Windows. Falls back to an encrypted file in `app_data_dir` when the OS store can't be
+/// reached, or when `GHOST_CREDENTIAL_STORE=file`. A token a previous run left in the other
+/// store is moved over, so switching stores doesn't log out.
+pub fn open(app_data_dir: &Path) -> Credentials {
+    let file_store = || Box::new(EncryptedFileStore::new(app_data_dir)) as Box<dyn CredentialStore>;
+    let keyring_store =
+        || KeyringStore::new().map(|store| Box::new(store) as Box<dyn CredentialStore>);
+    let (store, other) = match std::env::var(BACKEND_ENV).as_deref() {
+        Ok("file") => (file_store(), keyring_store().ok()),
+        _ => match keyring_store() {
+            Ok(store) => (store, Some(file_store())),
+            Err(e) => {
+                warn!(
+                    "OS secret store unavailable, using an encrypted file: {:?}",
+                    e
+                );
+                (file_store(), None)
+            }
+        },
+    };
+    info!("Storing credentials in the {}", store.name());
+    if let Some(other) = other {
+        if let Err(e) = migrate(other.as_ref(), store.as_ref()) {
+            warn!("Failed to move the JWT from the {}: {:?}", other.name(), e);
+        }
+    }
+    Credentials(store)
+}
+
+/// Move the token from `from` to `to` and delete it from `from`. A token already in `to` wins.
+fn migrate(from: &dyn CredentialStore, to: &dyn CredentialStore) -> Result<()> {
+    let Some(secret) = from.get()? else {
+        return Ok(());
+    };
+    if to.get()?.is_none() {
+        to.set(&secret)?;
+        info!(
+            "Moved the JWT from the {} to the {}",
+            from.name(),
+            to.name()
+        );
+    }
+    from.delete()
+}
+
+pub struct KeyringStore {
+    entry: keyring::Entry,
+}
+
+impl KeyringStore {
+    /// Connect to the OS secret store, failing if it can't be read, e.g. because no Secret
+    /// Service daemon runs
+    pub fn new() -> Result<Self> {
+        let entry = keyring::Entry::new(SERVICE, ACCOUNT)?;
+        match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(KeyringStore { entry }),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl CredentialStore for KeyringStore {
+    fn get(&self) -> Result<Option<String>> {
+        match self.entry.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e).context("Failed to read from the OS secret store"),
+        }
+    }
+
+    fn set(&self, secret: &str) -> Result<()> {
+        self.entry
+            .set_password(secret)
+            .context("Failed to write to the OS secret store")
+    }
+
+    fn delete(&self) -> Result<()> {
+        match self.entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e).context("Failed to delete from the OS secret store"),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "OS secret store"
+    }
+}
+
+/// AES-256-GCM encrypted secret in `credentials.bin`, with its random key in `credentials.key`
+/// in the same directory. Both are readable only by the user. Since the key sits next to the
+/// ciphertext, anything that can read the directory can decrypt the token: this keeps it out of
+/// plaintext config and out of backups that skip the key, not away from the user's own
+/// processes.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl EncryptedFileStore {
+    pub fn new(dir: &Path) -> Self {
+        EncryptedFileStore {
+            path: dir.join(CREDENTIALS_FILE),
+            key_path: dir.join(KEY_FILE),
+        }
+    }
+
+    fn cipher(&self, create: bool) -> Result<Option<Aes256Gcm>> {
+        let key = match fs::read(&self.key_path) {
+            Ok(key) => key,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !create => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key: [u8; 32] = rand::thread_rng().gen();
+                write_atomic(&self.key_path, &key, true)
+                    .context("Failed to write credentials key")?;
+                key.to_vec()
+            }
+            Err(e) => return Err(e).context("Failed to read credentials key"),
+        };
+        if key.len() != 32 {
+            return Err(anyhow!("Invalid credentials key"));
+        }
+        Ok(Some(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))))
+    }
+}
+
+impl CredentialStore for EncryptedFileStore {
+    fn get(&self) -> Result<Option<String>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read credentials"),
+        };
+        let Some(cipher) = self.cipher(false)? else {
+            return Err(anyhow!("Credentials key is missing"));
+        };
+        if contents.len() < NONCE_LEN {
+            return Err(anyhow!("Credentials file is truncated"));
+        }
+        let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+        let secret = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt credentials"))?;
+        Ok(Some(String::from_utf8(secret)?))
+    }
+
+    fn set(&self, secret: &str) -> Result<()> {
+        let cipher = self.cipher(true)?.unwrap();
+        let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+        let mut contents = nonce.to_vec();
+        contents.extend(
+            cipher
+                .encrypt(Nonce::from_slice(&nonce), secret.as_bytes())
+                .map_err(|_| anyhow!("Failed to encrypt credentials"))?,
+        );
+        write_atomic(&self.path, &contents, true).context("Failed to write credentials")
+    }
+
+    fn delete(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).context("Failed to delete credentials"),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "encrypted credentials file"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedFileStore::new(dir.path());
+        assert_eq!(store.get().unwrap(), None);
+
+        store.set("first.jwt.token").unwrap();
+        assert_eq!(store.get().unwrap().as_deref(), Some("first.jwt.token"));
+        let contents = fs::read(dir.path().join(CREDENTIALS_FILE)).unwrap();
+        assert!(!contents
+            .windows("first.jwt.token".len())
+            .any(|window| window == b"first.jwt.token"));
+
+        store.set("second.jwt.token").unwrap();
+        assert_eq!(store.get().unwrap().as_deref(), Some("second.jwt.token"));
+        // A new store with the same files reads the same token
+        let reopened = EncryptedFileStore::new(dir.path());
+        assert_eq!(reopened.get().unwrap().as_deref(), Some("second.jwt.token"));
+
+        store.delete().unwrap();
+        assert_eq!(store.get().unwrap(), None);
+        store.delete().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encrypted_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        EncryptedFileStore::new(dir.path()).set("jwt").unwrap();
+        for file in [CREDENTIALS_FILE, KEY_FILE] {
+            let mode = fs::metadata(dir.path().join(file))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", file);
+        }
+    }
+
+    #[test]
+    fn tampered_or_keyless_credentials_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedFileStore::new(dir.path());
+        store.set("jwt").unwrap();
+
+        let path = dir.path().join(CREDENTIALS_FILE);
+        let mut contents = fs::read(&path).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(&path, &contents).unwrap();
+        assert!(store.get().is_err());
+
+        fs::write(&path, [0; NONCE_LEN - 1]).unwrap();
+        assert!(store.get().is_err());
+
+        store.set("jwt").unwrap();
+        fs::remove_file(dir.path().join(KEY_FILE)).unwrap();
+        assert!(store.get().is_err());
+    }
+
+    #[test]
+    fn migrate_moves_the_token() {
+        let (from_dir, to_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let from = EncryptedFileStore::new(from_dir.path());
+        let to = EncryptedFileStore::new(to_dir.path());
+
+        migrate(&from, &to).unwrap();
+        assert_eq!(to.get().unwrap(), None);
+
+        from.set("old").unwrap();
+        migrate(&from, &to).unwrap();
+        assert_eq!(to.get().unwrap().as_deref(), Some("old"));
+        assert_eq!(from.get().unwrap(), None);
+
+        // The store in use keeps its own token
+        from.set("stale").unwrap();
+        migrate(&from, &to).unwrap();
+        assert_eq!(to.get().unwrap().as_deref(), Some("old"));
+        assert_eq!(from.get().unwrap(), None);
+    }
+}
@@ -0,0 +1,34 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Replace `path` with `contents` through a temp file next to it, so a crash leaves either the
+/// old or the new file. Only the current user can read a `private` file.
+pub fn write_atomic(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    let mut temp_path = OsString::from(path.as_os_str());
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut temp = options.open(&temp_path)?;
+    temp.write_all(contents)?;
+    // Without the sync the rename can reach the disk before the contents do
+    temp.sync_all()?;
+    fs::rename(&temp_path, path)?;
+
+    // The rename itself is only durable once the directory is synced
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
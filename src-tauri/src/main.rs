@@ -1,36 +1,92 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod api;
 mod auth;
+mod credentials;
+mod files;
 mod recording;
+mod settings;
 mod types;
 
 use std::fs;
 
-use log::{debug, LevelFilter};
+use log::{debug, warn, LevelFilter};
 use recording::recording::RecorderState;
 use tauri::Manager;
+use tauri_plugin_deep_link::DeepLinkExt;
 use tauri_plugin_log::{Target, TargetKind};
 
-use crate::recording::{start_recording, stop_recording};
-
-pub static BASE_URL: &str = "https://echo.i.inc";
-// pub static BASE_URL: &str = "http://localhost:8000";
+use crate::auth::{
+    current_user, handle_deep_link, login, logout, migrate_plaintext_jwt, spawn_token_refresh,
+};
+use crate::recording::{
+    get_recorder_status, list_displays, list_windows, pause_recording, resume_recording,
+    set_capture_target, set_fsync_policy, set_trajectory_sampling, start_recording, stop_recording,
+};
+use crate::settings::{
+    get_api_settings, get_capture_config, set_api_profile, set_capture_config, SettingsState,
+};
 
 fn main() {
     tauri::Builder::default()
         // .manage(RecorderState::new())
         .setup(|app| {
+            app.manage(SettingsState::load(&app.path().app_config_dir()?));
+
+            let app_data_dir = app.path().app_data_dir()?;
+            fs::create_dir_all(&app_data_dir)?;
+            app.manage(credentials::open(&app_data_dir));
+            if let Err(e) = migrate_plaintext_jwt(app.handle()) {
+                warn!("Failed to migrate the stored JWT: {}", e);
+            }
+
             app.manage(RecorderState::new(app.handle()));
+            if let Err(e) = app.state::<RecorderState>().recover_sessions() {
+                warn!("Failed to recover unfinished sessions: {:?}", e);
+            }
+
+            debug!("Custom directory: {:?}", app_data_dir);
+
+            // Installed apps get the scheme from the bundle, dev builds have to register it
+            #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
+            if let Err(e) = app.deep_link().register("invisibility") {
+                warn!("Failed to register the invisibility:// scheme: {:?}", e);
+            }
 
-            fs::create_dir_all(app.path().app_data_dir().unwrap()).unwrap();
+            let app_handle = app.handle().clone();
+            app.deep_link().on_open_url(move |event| {
+                for url in event.urls() {
+                    handle_deep_link(&app_handle, url.as_str());
+                }
+            });
 
-            let base_dir = app.path().app_data_dir().unwrap();
+            spawn_token_refresh(app.handle().clone());
 
-            debug!("Custom directory: {:?}", base_dir);
+            // On Linux and Windows the URL that launched the app is passed as an argument
+            match app.deep_link().get_current() {
+                Ok(urls) => {
+                    for url in urls.unwrap_or_default() {
+                        handle_deep_link(app.handle(), url.as_str());
+                    }
+                }
+                Err(e) => warn!("Failed to read the launch URL: {:?}", e),
+            }
 
             Ok(())
         })
+        // Has to come first: on Linux and Windows opening a deep link starts another process,
+        // which must hand the URL to this one instead of recovering the live session
+        .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
+            for arg in argv.iter().filter(|arg| arg.starts_with("invisibility://")) {
+                handle_deep_link(app, arg);
+            }
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.unminimize();
+                let _ = window.set_focus();
+            }
+        }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_log::Builder::new().build())
@@ -46,7 +102,25 @@ fn main() {
                 ])
                 .build(),
         )
-        .invoke_handler(tauri::generate_handler![start_recording, stop_recording])
+        .invoke_handler(tauri::generate_handler![
+            current_user,
+            get_api_settings,
+            get_capture_config,
+            get_recorder_status,
+            list_displays,
+            list_windows,
+            login,
+            logout,
+            pause_recording,
+            resume_recording,
+            set_api_profile,
+            set_capture_config,
+            set_capture_target,
+            set_fsync_policy,
+            set_trajectory_sampling,
+            start_recording,
+            stop_recording
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
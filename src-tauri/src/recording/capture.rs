@@ -0,0 +1,710 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::display::{CaptureArea, CoordinateTransform, DisplayInfo};
+
+/// H.264/H.265 encoder speed, slower presets give smaller chunks for more CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum Preset {
+    #[default]
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl Preset {
+    fn name(self) -> &'static str {
+        match self {
+            Preset::Ultrafast => "ultrafast",
+            Preset::Superfast => "superfast",
+            Preset::Veryfast => "veryfast",
+            Preset::Faster => "faster",
+            Preset::Fast => "fast",
+            Preset::Medium => "medium",
+            Preset::Slow => "slow",
+            Preset::Slower => "slower",
+            Preset::Veryslow => "veryslow",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum VideoCodec {
+    #[default]
+    Libx264,
+    Libx265,
+}
+
+impl VideoCodec {
+    fn encoder(self) -> &'static str {
+        match self {
+            VideoCodec::Libx264 => "libx264",
+            VideoCodec::Libx265 => "libx265",
+        }
+    }
+}
+
+/// How the encoder trades quality for size
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RateControl {
+    /// Constant quality, 0 is lossless and 51 the worst
+    Crf { crf: u8 },
+    /// Average bitrate in kbit/s, capped at the same rate
+    Bitrate { kbps: u32 },
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        RateControl::Crf { crf: 23 }
+    }
+}
+
+/// Container of the video chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum Container {
+    /// Matroska, readable up to the last frame written when FFmpeg is killed
+    #[default]
+    Mkv,
+    /// Fragmented MP4, so a cut off chunk is readable as well
+    Mp4,
+}
+
+impl Container {
+    const ALL: [Container; 2] = [Container::Mkv, Container::Mp4];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mkv => "mkv",
+            Container::Mp4 => "mp4",
+        }
+    }
+
+    /// Name of the FFmpeg muxer
+    pub fn muxer(self) -> &'static str {
+        match self {
+            Container::Mkv => "matroska",
+            Container::Mp4 => "mp4",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Container::Mkv => "video/x-matroska",
+            Container::Mp4 => "video/mp4",
+        }
+    }
+
+    /// The container of a chunk, by its extension
+    pub fn of_file(file_name: &str) -> Option<Container> {
+        let extension = Path::new(file_name).extension()?.to_str()?;
+        Container::ALL
+            .into_iter()
+            .find(|container| container.extension() == extension)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum PixelFormat {
+    #[default]
+    Yuv420p,
+    Yuv422p,
+    Yuv444p,
+}
+
+impl PixelFormat {
+    fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Yuv420p => "yuv420p",
+            PixelFormat::Yuv422p => "yuv422p",
+            PixelFormat::Yuv444p => "yuv444p",
+        }
+    }
+}
+
+/// How the screen is encoded. Stored in the settings file, takes effect from the next
+/// recording session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub fps: u32,
+    pub codec: VideoCodec,
+    pub preset: Preset,
+    pub rate_control: RateControl,
+    /// Length of a chunk, every chunk starts with a keyframe
+    pub segment_secs: u32,
+    pub container: Container,
+    pub pixel_format: PixelFormat,
+    /// Downscale the video by this factor, e.g. 0.5 for half the width and height
+    pub scale: Option<f64>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            fps: 30,
+            codec: VideoCodec::default(),
+            preset: Preset::default(),
+            rate_control: RateControl::default(),
+            segment_secs: 15,
+            container: Container::default(),
+            pixel_format: PixelFormat::default(),
+            scale: None,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Reject values FFmpeg would fail on, or that make chunks too large to upload
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=120).contains(&self.fps) {
+            return Err(anyhow!("fps must be between 1 and 120, got {}", self.fps));
+        }
+        match self.rate_control {
+            RateControl::Crf { crf } if crf > 51 => {
+                return Err(anyhow!("crf must be between 0 and 51, got {}", crf));
+            }
+            RateControl::Bitrate { kbps } if !(100..=100_000).contains(&kbps) => {
+                return Err(anyhow!(
+                    "Bitrate must be between 100 and 100000 kbit/s, got {}",
+                    kbps
+                ));
+            }
+            _ => {}
+        }
+        if !(1..=300).contains(&self.segment_secs) {
+            return Err(anyhow!(
+                "Segment length must be between 1 and 300 seconds, got {}",
+                self.segment_secs
+            ));
+        }
+        if let Some(scale) = self.scale {
+            if !(scale > 0.0 && scale <= 1.0) {
+                return Err(anyhow!(
+                    "Scale must be above 0 and at most 1, got {}",
+                    scale
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Factor from desktop pixels to video pixels
+    pub fn video_scale(&self) -> f64 {
+        self.scale.unwrap_or(1.0)
+    }
+}
+
+/// Platform whose screen grabber the arguments are for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    MacOs,
+    Windows,
+    Linux,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else {
+            Platform::Linux
+        }
+    }
+}
+
+/// Builds the FFmpeg arguments of a capture run. Video goes to segmented chunks, the timestamp
+/// of every frame to a separate file.
+#[derive(Debug, Clone)]
+pub struct CaptureArgs {
+    config: CaptureConfig,
+    platform: Platform,
+    area: CaptureArea,
+    /// Display the area is on. avfoundation records it whole, the area is cropped out.
+    display: Option<DisplayInfo>,
+    /// avfoundation device of the display
+    capture_device: u32,
+    /// X server x11grab connects to, like `$DISPLAY`
+    x11_display: String,
+    first_chunk: u32,
+    video_output: String,
+    segment_list: String,
+    timestamps: String,
+}
+
+impl CaptureArgs {
+    pub fn new(config: CaptureConfig, platform: Platform, area: CaptureArea) -> Self {
+        CaptureArgs {
+            config,
+            platform,
+            area,
+            display: None,
+            capture_device: 1,
+            x11_display: ":0.0".to_string(),
+            first_chunk: 0,
+            video_output: String::new(),
+            segment_list: String::new(),
+            timestamps: String::new(),
+        }
+    }
+
+    pub fn display(mut self, display: DisplayInfo) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    pub fn capture_device(mut self, capture_device: u32) -> Self {
+        self.capture_device = capture_device;
+        self
+    }
+
+    pub fn x11_display(mut self, x11_display: &str) -> Self {
+        self.x11_display = x11_display.to_string();
+        self
+    }
+
+    /// Number of the first chunk, runs after the first continue the numbering
+    pub fn first_chunk(mut self, first_chunk: u32) -> Self {
+        self.first_chunk = first_chunk;
+        self
+    }
+
+    /// Where the run writes to. `video_output` is a pattern like `chunk_%04d.mkv`.
+    pub fn outputs(mut self, video_output: &str, segment_list: &str, timestamps: &str) -> Self {
+        self.video_output = video_output.to_string();
+        self.segment_list = segment_list.to_string();
+        self.timestamps = timestamps.to_string();
+        self
+    }
+
+    pub fn area(&self) -> &CaptureArea {
+        &self.area
+    }
+
+    /// Maps event coordinates to pixels in the video, which may be downscaled
+    pub fn video_transform(&self) -> CoordinateTransform {
+        CoordinateTransform {
+            scale: self.config.video_scale(),
+            ..self.area.transform()
+        }
+    }
+
+    /// File name pattern of the chunks
+    pub fn chunk_pattern(&self) -> String {
+        format!("chunk_%04d.{}", self.config.container.extension())
+    }
+
+    pub fn build(&self) -> Vec<String> {
+        let config = &self.config;
+        let area = &self.area;
+        let mut args: Vec<String> = Vec::new();
+        let mut push = |values: &[&str]| args.extend(values.iter().map(|value| value.to_string()));
+
+        // Progress reports on stdout are the watchdog's heartbeat, stderr is left to the log
+        push(&["-progress", "pipe:1", "-nostats"]);
+        // Keep the frames `mpdecimate` leaves, at the timestamps they were captured
+        push(&["-vsync", "0"]);
+
+        let fps = config.fps.to_string();
+        let size = format!("{}x{}", area.width, area.height);
+        let (x, y) = (area.x.to_string(), area.y.to_string());
+        match self.platform {
+            // avfoundation captures whole screens, which are listed in the same order as the
+            // displays
+            Platform::MacOs => push(&[
+                "-f",
+                "avfoundation",
+                "-capture_cursor",
+                "1",
+                "-framerate",
+                &fps,
+                "-i",
+                &format!("{}:none", self.capture_device),
+            ]),
+            Platform::Windows => push(&[
+                "-f",
+                "gdigrab",
+                "-draw_mouse",
+                "1",
+                "-framerate",
+                &fps,
+                "-offset_x",
+                &x,
+                "-offset_y",
+                &y,
+                "-video_size",
+                &size,
+                "-i",
+                "desktop",
+            ]),
+            // The area's offset goes with the display, `:1+100,50`
+            Platform::Linux => push(&[
+                "-f",
+                "x11grab",
+                "-draw_mouse",
+                "1",
+                "-framerate",
+                &fps,
+                "-video_size",
+                &size,
+                "-i",
+                &format!("{}+{},{}", self.x11_display, x, y),
+            ]),
+        }
+
+        push(&[
+            "-filter_complex",
+            &format!(
+                "{}{}settb=1/1000,setpts='RTCTIME/1000',mpdecimate,split=2[out][ts]",
+                self.crop_filter(),
+                self.scale_filter()
+            ),
+        ]);
+        push(&["-map", "[out]", "-c:v", config.codec.encoder()]);
+        push(&["-preset", config.preset.name()]);
+        match config.rate_control {
+            RateControl::Crf { crf } => push(&["-crf", &crf.to_string()]),
+            RateControl::Bitrate { kbps } => push(&[
+                "-b:v",
+                &format!("{}k", kbps),
+                "-maxrate",
+                &format!("{}k", kbps),
+                "-bufsize",
+                &format!("{}k", kbps * 2),
+            ]),
+        }
+        push(&["-pix_fmt", config.pixel_format.name(), "-threads", "0"]);
+        // Frames are stamped with the wall clock, so keyframes are spaced from the last one
+        // rather than from zero. A segment can only be cut at a keyframe.
+        push(&[
+            "-force_key_frames",
+            &format!(
+                "expr:if(isnan(prev_forced_t),1,gte(t,prev_forced_t+{}))",
+                config.segment_secs
+            ),
+        ]);
+        push(&[
+            "-f",
+            "segment",
+            "-segment_time",
+            &config.segment_secs.to_string(),
+            "-segment_start_number",
+            &self.first_chunk.to_string(),
+            "-reset_timestamps",
+            "1",
+            "-segment_format",
+            config.container.muxer(),
+        ]);
+        if config.container == Container::Mp4 {
+            push(&[
+                "-segment_format_options",
+                "movflags=frag_keyframe+empty_moov+default_base_moof",
+            ]);
+        }
+        push(&[
+            "-segment_list_type",
+            "csv",
+            "-segment_list",
+            &self.segment_list,
+            &self.video_output,
+        ]);
+        push(&["-map", "[ts]", "-f", "mkvtimestamp_v2", &self.timestamps]);
+        args
+    }
+
+    /// avfoundation can only record whole screens, so regions on macOS are cropped out of the
+    /// screen they are on. Other platforms grab the area directly.
+    fn crop_filter(&self) -> String {
+        let (Platform::MacOs, Some(display)) = (self.platform, &self.display) else {
+            return String::new();
+        };
+        let area = &self.area;
+        if (area.x, area.y, area.width, area.height)
+            == (display.x, display.y, display.width, display.height)
+        {
+            return String::new();
+        }
+        format!(
+            "crop={}:{}:{}:{},",
+            area.width,
+            area.height,
+            area.x - display.x,
+            area.y - display.y
+        )
+    }
+
+    /// Downscale, keeping the sides even as the encoders require
+    fn scale_filter(&self) -> String {
+        match self.config.scale {
+            Some(scale) if scale < 1.0 => format!(
+                "scale=trunc(iw*{scale}/2)*2:trunc(ih*{scale}/2)*2,",
+                scale = scale
+            ),
+            _ => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area() -> CaptureArea {
+        CaptureArea {
+            x: 100,
+            y: 50,
+            width: 1280,
+            height: 720,
+            displays: vec![0],
+        }
+    }
+
+    fn display() -> DisplayInfo {
+        DisplayInfo {
+            index: 0,
+            name: None,
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            scale_factor: 1.0,
+            primary: true,
+        }
+    }
+
+    fn build(config: CaptureConfig, platform: Platform) -> Vec<String> {
+        CaptureArgs::new(config, platform, area())
+            .display(display())
+            .capture_device(3)
+            .first_chunk(7)
+            .outputs("rec/chunk_%04d.mkv", "segments.csv", "timestamps.txt")
+            .build()
+    }
+
+    /// The arguments after the input, which are the same on every platform
+    fn output_args(filter: &str) -> Vec<&str> {
+        vec![
+            "-filter_complex",
+            filter,
+            "-map",
+            "[out]",
+            "-c:v",
+            "libx264",
+            "-preset",
+            "ultrafast",
+            "-crf",
+            "23",
+            "-pix_fmt",
+            "yuv420p",
+            "-threads",
+            "0",
+            "-force_key_frames",
+            "expr:if(isnan(prev_forced_t),1,gte(t,prev_forced_t+15))",
+            "-f",
+            "segment",
+            "-segment_time",
+            "15",
+            "-segment_start_number",
+            "7",
+            "-reset_timestamps",
+            "1",
+            "-segment_format",
+            "matroska",
+            "-segment_list_type",
+            "csv",
+            "-segment_list",
+            "segments.csv",
+            "rec/chunk_%04d.mkv",
+            "-map",
+            "[ts]",
+            "-f",
+            "mkvtimestamp_v2",
+            "timestamps.txt",
+        ]
+    }
+
+    const GLOBAL_ARGS: [&str; 5] = ["-progress", "pipe:1", "-nostats", "-vsync", "0"];
+    const FILTER: &str = "settb=1/1000,setpts='RTCTIME/1000',mpdecimate,split=2[out][ts]";
+
+    #[test]
+    fn linux_grabs_the_area() {
+        let mut expected = GLOBAL_ARGS.to_vec();
+        expected.extend([
+            "-f",
+            "x11grab",
+            "-draw_mouse",
+            "1",
+            "-framerate",
+            "30",
+            "-video_size",
+            "1280x720",
+            "-i",
+            ":0.0+100,50",
+        ]);
+        expected.extend(output_args(FILTER));
+        assert_eq!(build(CaptureConfig::default(), Platform::Linux), expected);
+    }
+
+    #[test]
+    fn linux_grabs_from_the_given_display() {
+        let args = CaptureArgs::new(CaptureConfig::default(), Platform::Linux, area())
+            .x11_display(":1")
+            .build();
+        let input = args.iter().position(|arg| arg == "-i").unwrap() + 1;
+        assert_eq!(args[input], ":1+100,50");
+    }
+
+    #[test]
+    fn windows_grabs_the_area() {
+        let mut expected = GLOBAL_ARGS.to_vec();
+        expected.extend([
+            "-f",
+            "gdigrab",
+            "-draw_mouse",
+            "1",
+            "-framerate",
+            "30",
+            "-offset_x",
+            "100",
+            "-offset_y",
+            "50",
+            "-video_size",
+            "1280x720",
+            "-i",
+            "desktop",
+        ]);
+        expected.extend(output_args(FILTER));
+        assert_eq!(build(CaptureConfig::default(), Platform::Windows), expected);
+    }
+
+    #[test]
+    fn macos_crops_the_area_out_of_the_screen() {
+        let mut expected = GLOBAL_ARGS.to_vec();
+        expected.extend([
+            "-f",
+            "avfoundation",
+            "-capture_cursor",
+            "1",
+            "-framerate",
+            "30",
+            "-i",
+            "3:none",
+        ]);
+        let filter = format!("crop=1280:720:100:50,{}", FILTER);
+        expected.extend(output_args(&filter));
+        assert_eq!(build(CaptureConfig::default(), Platform::MacOs), expected);
+    }
+
+    #[test]
+    fn macos_records_a_whole_screen_uncropped() {
+        let whole = CaptureArea {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            displays: vec![0],
+        };
+        let args = CaptureArgs::new(CaptureConfig::default(), Platform::MacOs, whole)
+            .display(display())
+            .build();
+        assert!(args.iter().any(|arg| arg.as_str() == FILTER));
+    }
+
+    #[test]
+    fn config_changes_the_encoding() {
+        let config = CaptureConfig {
+            fps: 10,
+            codec: VideoCodec::Libx265,
+            preset: Preset::Medium,
+            rate_control: RateControl::Bitrate { kbps: 2000 },
+            segment_secs: 60,
+            container: Container::Mp4,
+            pixel_format: PixelFormat::Yuv444p,
+            scale: Some(0.5),
+        };
+        let args = build(config, Platform::Linux).join(" ");
+        for expected in [
+            "-framerate 10",
+            "scale=trunc(iw*0.5/2)*2:trunc(ih*0.5/2)*2,settb",
+            "-c:v libx265 -preset medium -b:v 2000k -maxrate 2000k -bufsize 4000k",
+            "-pix_fmt yuv444p",
+            "gte(t,prev_forced_t+60)",
+            "-segment_time 60",
+            "-segment_format mp4 -segment_format_options movflags=",
+        ] {
+            assert!(args.contains(expected), "{} in {}", expected, args);
+        }
+        assert!(!args.contains("-crf"));
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_values() {
+        assert!(CaptureConfig::default().validate().is_ok());
+        let invalid = [
+            CaptureConfig {
+                fps: 0,
+                ..Default::default()
+            },
+            CaptureConfig {
+                rate_control: RateControl::Crf { crf: 52 },
+                ..Default::default()
+            },
+            CaptureConfig {
+                rate_control: RateControl::Bitrate { kbps: 0 },
+                ..Default::default()
+            },
+            CaptureConfig {
+                segment_secs: 0,
+                ..Default::default()
+            },
+            CaptureConfig {
+                scale: Some(1.5),
+                ..Default::default()
+            },
+            CaptureConfig {
+                scale: Some(f64::NAN),
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn missing_settings_take_the_defaults() {
+        let config: CaptureConfig = serde_json::from_str(r#"{"fps": 15}"#).unwrap();
+        assert_eq!(
+            config,
+            CaptureConfig {
+                fps: 15,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(RateControl::default()).unwrap(),
+            serde_json::json!({"mode": "crf", "crf": 23})
+        );
+    }
+
+    #[test]
+    fn container_of_chunk_files() {
+        assert_eq!(Container::of_file("chunk_0001.mkv"), Some(Container::Mkv));
+        assert_eq!(Container::of_file("chunk_0001.mp4"), Some(Container::Mp4));
+        assert_eq!(Container::of_file("chunk_0001.mkv.repaired"), None);
+    }
+}
@@ -0,0 +1,538 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::types::{FocusAction, WindowContext};
+
+/// How long a looked up window context is reused for mouse motion before asking the window
+/// system again
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the input hook waits for a window lookup before going with the last known window
+const LOOKUP_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Source of the focused window for input events
+pub trait ContextProvider: Send {
+    fn active_window(&mut self) -> Option<WindowContext>;
+
+    /// Look up a window by its `window_id`
+    fn window(&mut self, window_id: u64) -> Option<WindowContext>;
+
+    /// Top level windows that can be picked as a capture target
+    fn windows(&mut self) -> Vec<WindowContext>;
+
+    /// Whether windows can be looked up at all, and so recorded with `CaptureTarget::Window`
+    fn supports_windows(&self) -> bool;
+}
+
+/// Used on platforms without a provider yet, events are recorded without window context
+pub struct NoContextProvider;
+
+impl ContextProvider for NoContextProvider {
+    fn active_window(&mut self) -> Option<WindowContext> {
+        None
+    }
+
+    fn window(&mut self, _window_id: u64) -> Option<WindowContext> {
+        None
+    }
+
+    fn windows(&mut self) -> Vec<WindowContext> {
+        Vec::new()
+    }
+
+    fn supports_windows(&self) -> bool {
+        false
+    }
+}
+
+/// Provider for the current platform, falls back to `NoContextProvider` if the window system
+/// can't be reached
+pub fn default_provider() -> Box<dyn ContextProvider> {
+    #[cfg(target_os = "linux")]
+    {
+        match x11::X11ContextProvider::connect(None) {
+            Ok(provider) => return Box::new(provider),
+            Err(e) => log::warn!("Window context unavailable: {:?}", e),
+        }
+    }
+
+    Box::new(NoContextProvider)
+}
+
+/// The event for focus moving from `previous` to `current`, none if the same window stays
+/// focused
+pub fn focus_change(
+    previous: &Option<WindowContext>,
+    current: &Option<WindowContext>,
+) -> Option<FocusAction> {
+    let window_id = |window: &Option<WindowContext>| window.as_ref().map(|w| w.window_id);
+    (window_id(previous) != window_id(current)).then(|| FocusAction {
+        previous: previous.clone(),
+    })
+}
+
+/// Caches the provider's answer so bursts of mouse motion don't each cost a window system
+/// round trip
+pub struct WindowTracker {
+    provider: Box<dyn ContextProvider>,
+    current: Option<WindowContext>,
+    refreshed_at: Option<Instant>,
+}
+
+impl WindowTracker {
+    pub fn new(provider: Box<dyn ContextProvider>) -> Self {
+        WindowTracker {
+            provider,
+            current: None,
+            refreshed_at: None,
+        }
+    }
+
+    /// The focused window, as looked up at most `REFRESH_INTERVAL` ago
+    pub fn current(&mut self) -> Option<WindowContext> {
+        let stale = self
+            .refreshed_at
+            .is_none_or(|refreshed_at| refreshed_at.elapsed() >= REFRESH_INTERVAL);
+        if stale {
+            return self.refresh();
+        }
+        self.current.clone()
+    }
+
+    /// The focused window, looked up now. Clicks and key presses often come right after focus
+    /// moved, so they don't use the cache.
+    pub fn refresh(&mut self) -> Option<WindowContext> {
+        self.current = self.provider.active_window();
+        self.refreshed_at = Some(Instant::now());
+        self.current.clone()
+    }
+}
+
+/// Runs a `WindowTracker` on a thread of its own, so the input hook's callback never waits on
+/// the window system for longer than `LOOKUP_TIMEOUT`
+pub struct WindowWatcher {
+    /// Lookup number and whether it has to be fresh, see `WindowTracker::refresh`
+    requests: Sender<(u64, bool)>,
+    replies: Receiver<(u64, Option<WindowContext>)>,
+    next_request: u64,
+    last: Option<WindowContext>,
+}
+
+impl WindowWatcher {
+    pub fn new(provider: Box<dyn ContextProvider>) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<(u64, bool)>();
+        let (reply_sender, replies) = mpsc::channel();
+        thread::spawn(move || {
+            let mut tracker = WindowTracker::new(provider);
+            while let Ok((mut id, mut fresh)) = request_receiver.recv() {
+                // Requests that queued up behind a slow lookup get a single answer
+                while let Ok((next_id, next_fresh)) = request_receiver.try_recv() {
+                    id = next_id;
+                    fresh |= next_fresh;
+                }
+                let window = if fresh {
+                    tracker.refresh()
+                } else {
+                    tracker.current()
+                };
+                if reply_sender.send((id, window)).is_err() {
+                    break;
+                }
+            }
+        });
+        WindowWatcher {
+            requests,
+            replies,
+            next_request: 0,
+            last: None,
+        }
+    }
+
+    /// The focused window as `WindowTracker::current` sees it
+    pub fn current(&mut self) -> Option<WindowContext> {
+        self.lookup(false)
+    }
+
+    /// The focused window as `WindowTracker::refresh` sees it
+    pub fn refresh(&mut self) -> Option<WindowContext> {
+        self.lookup(true)
+    }
+
+    /// Ask the tracker thread and wait for its answer, or for `LOOKUP_TIMEOUT`. Answers that
+    /// come too late still update the last known window for the next lookup.
+    fn lookup(&mut self, fresh: bool) -> Option<WindowContext> {
+        self.next_request += 1;
+        let id = self.next_request;
+        if self.requests.send((id, fresh)).is_err() {
+            return self.last.clone();
+        }
+        let deadline = Instant::now() + LOOKUP_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.replies.recv_timeout(timeout) {
+                Ok((reply_id, window)) => {
+                    self.last = window;
+                    if reply_id >= id {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    debug!("Window lookup timed out, using the last known window");
+                    break;
+                }
+            }
+        }
+        self.last.clone()
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub mod x11 {
+    use anyhow::{Context, Result};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+    use x11rb::rust_connection::RustConnection;
+
+    use super::ContextProvider;
+    use crate::types::WindowContext;
+
+    /// Reads the focused window from the EWMH `_NET_ACTIVE_WINDOW` property of the root window
+    pub struct X11ContextProvider {
+        conn: RustConnection,
+        root: Window,
+        net_active_window: Atom,
+        net_client_list: Atom,
+        net_wm_name: Atom,
+        utf8_string: Atom,
+    }
+
+    impl X11ContextProvider {
+        /// Connect to `display`, or `$DISPLAY` when `None`
+        pub fn connect(display: Option<&str>) -> Result<Self> {
+            let (conn, screen_num) =
+                x11rb::connect(display).context("Failed to connect to X server")?;
+            let root = conn.setup().roots[screen_num].root;
+            let intern =
+                |name: &[u8]| -> Result<Atom> { Ok(conn.intern_atom(false, name)?.reply()?.atom) };
+            let net_active_window = intern(b"_NET_ACTIVE_WINDOW")?;
+            let net_client_list = intern(b"_NET_CLIENT_LIST")?;
+            let net_wm_name = intern(b"_NET_WM_NAME")?;
+            let utf8_string = intern(b"UTF8_STRING")?;
+
+            Ok(X11ContextProvider {
+                conn,
+                root,
+                net_active_window,
+                net_client_list,
+                net_wm_name,
+                utf8_string,
+            })
+        }
+
+        fn property(&self, window: Window, property: Atom, type_: Atom) -> Option<Vec<u8>> {
+            let reply = self
+                .conn
+                .get_property(false, window, property, type_, 0, u32::MAX)
+                .ok()?
+                .reply()
+                .ok()?;
+            (reply.format == 8 && !reply.value.is_empty()).then_some(reply.value)
+        }
+
+        fn active_window_id(&self) -> Option<Window> {
+            let reply = self
+                .conn
+                .get_property(
+                    false,
+                    self.root,
+                    self.net_active_window,
+                    AtomEnum::WINDOW,
+                    0,
+                    1,
+                )
+                .ok()?
+                .reply()
+                .ok()?;
+            let window = reply.value32()?.next()?;
+            (window != 0).then_some(window)
+        }
+
+        fn window_title(&self, window: Window) -> Option<String> {
+            self.property(window, self.net_wm_name, self.utf8_string)
+                .or_else(|| {
+                    self.property(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())
+                })
+                .map(|title| String::from_utf8_lossy(&title).into_owned())
+        }
+
+        /// The class part of `WM_CLASS`, e.g. `firefox` or `Code`
+        fn app_name(&self, window: Window) -> Option<String> {
+            let class =
+                self.property(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?;
+            // WM_CLASS is two null terminated strings, instance then class
+            let mut parts = class
+                .split(|byte| *byte == 0)
+                .filter(|part| !part.is_empty());
+            let instance = parts.next();
+            parts
+                .next()
+                .or(instance)
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+        }
+    }
+
+    impl ContextProvider for X11ContextProvider {
+        fn active_window(&mut self) -> Option<WindowContext> {
+            let window = self.active_window_id()?;
+            self.window(u64::from(window))
+        }
+
+        fn window(&mut self, window_id: u64) -> Option<WindowContext> {
+            let window = Window::try_from(window_id).ok()?;
+            let geometry = self.conn.get_geometry(window).ok()?.reply().ok()?;
+            // Geometry is relative to the parent (often a window manager frame), so translate the
+            // origin to root coordinates
+            let origin = self
+                .conn
+                .translate_coordinates(window, self.root, 0, 0)
+                .ok()?
+                .reply()
+                .ok()?;
+
+            Some(WindowContext {
+                window_id: u64::from(window),
+                app_name: self.app_name(window),
+                window_title: self.window_title(window),
+                x: i32::from(origin.dst_x),
+                y: i32::from(origin.dst_y),
+                width: u32::from(geometry.width),
+                height: u32::from(geometry.height),
+            })
+        }
+
+        fn windows(&mut self) -> Vec<WindowContext> {
+            let client_list = self
+                .conn
+                .get_property(
+                    false,
+                    self.root,
+                    self.net_client_list,
+                    AtomEnum::WINDOW,
+                    0,
+                    u32::MAX,
+                )
+                .ok()
+                .and_then(|cookie| cookie.reply().ok());
+            let Some(client_list) = client_list else {
+                return Vec::new();
+            };
+            let Some(window_ids) = client_list.value32() else {
+                return Vec::new();
+            };
+
+            let window_ids: Vec<Window> = window_ids.collect();
+            window_ids
+                .into_iter()
+                .filter_map(|window| self.window(u64::from(window)))
+                .collect()
+        }
+
+        fn supports_windows(&self) -> bool {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Answers with the window in `active` after `delay`
+    struct SlowProvider {
+        active: Arc<Mutex<Option<WindowContext>>>,
+        delay: Duration,
+    }
+
+    impl ContextProvider for SlowProvider {
+        fn active_window(&mut self) -> Option<WindowContext> {
+            thread::sleep(self.delay);
+            self.active.lock().unwrap().clone()
+        }
+
+        fn window(&mut self, _window_id: u64) -> Option<WindowContext> {
+            None
+        }
+
+        fn windows(&mut self) -> Vec<WindowContext> {
+            Vec::new()
+        }
+
+        fn supports_windows(&self) -> bool {
+            false
+        }
+    }
+
+    fn window(window_id: u64) -> Option<WindowContext> {
+        Some(WindowContext {
+            window_id,
+            app_name: None,
+            window_title: None,
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 100,
+        })
+    }
+
+    fn watcher(delay: Duration) -> (WindowWatcher, Arc<Mutex<Option<WindowContext>>>) {
+        let active = Arc::new(Mutex::new(window(1)));
+        let provider = SlowProvider {
+            active: active.clone(),
+            delay,
+        };
+        (WindowWatcher::new(Box::new(provider)), active)
+    }
+
+    #[test]
+    fn watcher_answers_fast_lookups() {
+        let (mut watcher, active) = watcher(Duration::ZERO);
+        assert_eq!(watcher.refresh(), window(1));
+        *active.lock().unwrap() = window(2);
+        // Cached for mouse motion, looked up again for clicks and keys
+        assert_eq!(watcher.current(), window(1));
+        assert_eq!(watcher.refresh(), window(2));
+    }
+
+    #[test]
+    fn watcher_doesnt_wait_for_slow_lookups() {
+        let (mut watcher, _) = watcher(LOOKUP_TIMEOUT * 5);
+        let started = Instant::now();
+        assert_eq!(watcher.refresh(), None);
+        assert!(started.elapsed() < LOOKUP_TIMEOUT * 4);
+
+        // The next lookup times out too, but by then the late answer is in
+        thread::sleep(LOOKUP_TIMEOUT * 6);
+        assert_eq!(watcher.refresh(), window(1));
+    }
+
+    #[cfg(target_os = "linux")]
+    mod x11 {
+        use x11rb::connection::Connection;
+        use x11rb::protocol::xproto::{
+            AtomEnum, ConnectionExt, CreateWindowAux, PropMode, Window, WindowClass,
+        };
+        use x11rb::wrapper::ConnectionExt as _;
+        use x11rb::COPY_DEPTH_FROM_PARENT;
+
+        use super::super::x11::X11ContextProvider;
+        use super::super::{focus_change, WindowTracker};
+        use crate::types::WindowContext;
+
+        #[test]
+        #[ignore = "needs an X server, run with `xvfb-run cargo test -- --ignored`"]
+        fn x11_active_window() {
+            let display = std::env::var("DISPLAY").expect("DISPLAY must be set");
+            let (conn, screen_num) = x11rb::connect(Some(&display)).unwrap();
+            let root = conn.setup().roots[screen_num].root;
+            let net_active_window = conn
+                .intern_atom(false, b"_NET_ACTIVE_WINDOW")
+                .unwrap()
+                .reply()
+                .unwrap()
+                .atom;
+            let create_window = |x: i16, title: &str, class: &[u8]| -> Window {
+                let window = conn.generate_id().unwrap();
+                conn.create_window(
+                    COPY_DEPTH_FROM_PARENT,
+                    window,
+                    root,
+                    x,
+                    20,
+                    300,
+                    200,
+                    0,
+                    WindowClass::INPUT_OUTPUT,
+                    0,
+                    &CreateWindowAux::new(),
+                )
+                .unwrap();
+                conn.change_property8(
+                    PropMode::REPLACE,
+                    window,
+                    AtomEnum::WM_NAME,
+                    AtomEnum::STRING,
+                    title.as_bytes(),
+                )
+                .unwrap();
+                conn.change_property8(
+                    PropMode::REPLACE,
+                    window,
+                    AtomEnum::WM_CLASS,
+                    AtomEnum::STRING,
+                    class,
+                )
+                .unwrap();
+                conn.map_window(window).unwrap();
+                window
+            };
+            // What a window manager does on a focus change. The round trip makes sure the server
+            // is done before the provider's own connection asks.
+            let activate = |window: Window| {
+                conn.change_property32(
+                    PropMode::REPLACE,
+                    root,
+                    net_active_window,
+                    AtomEnum::WINDOW,
+                    &[window],
+                )
+                .unwrap();
+                conn.get_input_focus().unwrap().reply().unwrap();
+            };
+
+            let editor = create_window(10, "Editor", b"code\0Code\0");
+            let browser = create_window(400, "Browser", b"navigator\0firefox\0");
+            activate(editor);
+
+            let provider = X11ContextProvider::connect(Some(&display)).unwrap();
+            let mut tracker = WindowTracker::new(Box::new(provider));
+            let first = tracker.current();
+            assert_eq!(
+                first,
+                Some(WindowContext {
+                    window_id: u64::from(editor),
+                    app_name: Some("Code".to_string()),
+                    window_title: Some("Editor".to_string()),
+                    x: 10,
+                    y: 20,
+                    width: 300,
+                    height: 200,
+                })
+            );
+            assert!(focus_change(&first, &tracker.current()).is_none());
+
+            // A click right after the switch sees the new window, though the cache is still fresh
+            activate(browser);
+            let second = tracker.refresh();
+            assert_eq!(
+                second.as_ref().map(|window| window.window_id),
+                Some(u64::from(browser))
+            );
+            assert_eq!(
+                second
+                    .as_ref()
+                    .and_then(|window| window.app_name.as_deref()),
+                Some("firefox")
+            );
+            let focus_action = focus_change(&first, &second).expect("focus moved");
+            assert_eq!(focus_action.previous, first);
+
+            conn.destroy_window(editor).unwrap();
+            conn.destroy_window(browser).unwrap();
+            conn.flush().unwrap();
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tauri::WebviewWindow;
+
+/// A monitor as reported by the window system. Position and size are in global desktop pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f64,
+    pub primary: bool,
+}
+
+impl DisplayInfo {
+    /// Whether a point in the platform's input coordinates (points on macOS, pixels elsewhere)
+    /// falls on this display
+    fn contains_input_point(&self, x: f64, y: f64) -> bool {
+        let scale = input_scale(self.scale_factor);
+        let (left, top) = (self.x as f64 / scale, self.y as f64 / scale);
+        let (width, height) = (self.width as f64 / scale, self.height as f64 / scale);
+        x >= left && x < left + width && y >= top && y < top + height
+    }
+}
+
+/// What part of the desktop is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "target", rename_all = "snake_case")]
+pub enum CaptureTarget {
+    Display {
+        selection: DisplaySelection,
+    },
+    /// A fixed rectangle in global desktop pixels
+    Rectangle {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    },
+    /// The bounds of a window when the recording starts, see `list_windows`. Input is only
+    /// recorded while that window has focus or the cursor is over it.
+    Window {
+        window_id: u64,
+    },
+}
+
+impl Default for CaptureTarget {
+    fn default() -> Self {
+        CaptureTarget::Display {
+            selection: DisplaySelection::default(),
+        }
+    }
+}
+
+/// Which displays end up in the video
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DisplaySelection {
+    #[default]
+    Primary,
+    Display {
+        index: usize,
+    },
+    /// The bounding box of every display. Not supported by avfoundation, macOS records the
+    /// primary display instead.
+    All,
+}
+
+/// Maps global desktop pixels to pixels in the recorded video:
+/// `video_x = (x - offset_x) * scale`, `video_y = (y - offset_y) * scale`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CoordinateTransform {
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub scale: f64,
+}
+
+/// The part of the desktop that is recorded, in global desktop pixels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureArea {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Indices of the recorded displays
+    pub displays: Vec<usize>,
+}
+
+impl CaptureArea {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x
+            && x < self.x + self.width as i32
+            && y >= self.y
+            && y < self.y + self.height as i32
+    }
+
+    fn intersects(&self, other: &CaptureArea) -> bool {
+        self.x < other.x + other.width as i32
+            && other.x < self.x + self.width as i32
+            && self.y < other.y + other.height as i32
+            && other.y < self.y + self.height as i32
+    }
+
+    pub fn transform(&self) -> CoordinateTransform {
+        CoordinateTransform {
+            offset_x: self.x,
+            offset_y: self.y,
+            scale: 1.0,
+        }
+    }
+}
+
+pub fn list_displays(window: &WebviewWindow) -> Result<Vec<DisplayInfo>> {
+    let primary_position = window
+        .primary_monitor()?
+        .map(|monitor| (monitor.position().x, monitor.position().y));
+
+    let displays = window
+        .available_monitors()?
+        .iter()
+        .enumerate()
+        .map(|(index, monitor)| DisplayInfo {
+            index,
+            name: monitor.name().cloned(),
+            x: monitor.position().x,
+            y: monitor.position().y,
+            width: monitor.size().width,
+            height: monitor.size().height,
+            scale_factor: monitor.scale_factor(),
+            primary: primary_position == Some((monitor.position().x, monitor.position().y)),
+        })
+        .collect();
+    Ok(displays)
+}
+
+/// Number of the `Capture screen N` avfoundation lists for a display. avfoundation goes by the
+/// active display list, which isn't necessarily the order monitors are reported in, so the
+/// display is found in it by position. Bounds there are in points, display positions in
+/// pixels.
+#[cfg(target_os = "macos")]
+pub fn avfoundation_screen(display: &DisplayInfo) -> Result<usize> {
+    use core_graphics::display::CGDisplay;
+
+    let ids = CGDisplay::active_displays()
+        .map_err(|e| anyhow!("Failed to list the active displays: {}", e))?;
+    ids.iter()
+        .position(|id| {
+            let origin = CGDisplay::new(*id).bounds().origin;
+            (origin.x * display.scale_factor).round() as i32 == display.x
+                && (origin.y * display.scale_factor).round() as i32 == display.y
+        })
+        .ok_or_else(|| anyhow!("Display {} is not an active display", display.index))
+}
+
+/// Resolve a display selection against the connected displays
+pub fn display_area(selection: DisplaySelection, displays: &[DisplayInfo]) -> Result<CaptureArea> {
+    let primary = || {
+        displays
+            .iter()
+            .find(|display| display.primary)
+            .or_else(|| displays.first())
+            .ok_or_else(|| anyhow!("No displays found"))
+    };
+    match selection {
+        DisplaySelection::Primary => Ok(display_rect(primary()?)),
+        DisplaySelection::Display { index } => displays
+            .get(index)
+            .map(display_rect)
+            .ok_or_else(|| anyhow!("Display {} not found", index)),
+        DisplaySelection::All if cfg!(target_os = "macos") => {
+            log::warn!("Recording all displays is not supported on macOS, using the primary");
+            Ok(display_rect(primary()?))
+        }
+        DisplaySelection::All => {
+            let (left, top, right, bottom) = desktop_bounds(displays)?;
+            Ok(CaptureArea {
+                x: left,
+                y: top,
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+                displays: displays.iter().map(|display| display.index).collect(),
+            })
+        }
+    }
+}
+
+/// Resolve a rectangle in global desktop pixels, clipped to the desktop. avfoundation records
+/// whole screens, so on macOS the rectangle is clipped to the display it starts on and cropped
+/// out of that screen.
+pub fn region_area(
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    displays: &[DisplayInfo],
+) -> Result<CaptureArea> {
+    let (mut left, mut top, mut right, mut bottom) = desktop_bounds(displays)?;
+    if cfg!(target_os = "macos") {
+        let display = displays
+            .iter()
+            .find(|display| display_rect(display).contains(x, y))
+            .ok_or_else(|| anyhow!("Region {},{} is not on any display", x, y))?;
+        (left, top) = (display.x, display.y);
+        (right, bottom) = (
+            display.x + display.width as i32,
+            display.y + display.height as i32,
+        );
+    }
+
+    let (x0, y0) = (x.max(left), y.max(top));
+    let (x1, y1) = (
+        (x + width as i32).min(right),
+        (y + height as i32).min(bottom),
+    );
+    // libx264 with yuv420p needs even dimensions
+    let (width, height) = (
+        ((x1 - x0).max(0) as u32) & !1,
+        ((y1 - y0).max(0) as u32) & !1,
+    );
+    if width == 0 || height == 0 {
+        return Err(anyhow!("Region is outside of the desktop"));
+    }
+
+    let area = CaptureArea {
+        x: x0,
+        y: y0,
+        width,
+        height,
+        displays: Vec::new(),
+    };
+    let displays = displays
+        .iter()
+        .filter(|display| area.intersects(&display_rect(display)))
+        .map(|display| display.index)
+        .collect();
+    Ok(CaptureArea { displays, ..area })
+}
+
+fn display_rect(display: &DisplayInfo) -> CaptureArea {
+    CaptureArea {
+        x: display.x,
+        y: display.y,
+        width: display.width,
+        height: display.height,
+        displays: vec![display.index],
+    }
+}
+
+/// Left, top, right and bottom edge of the bounding box of every display
+fn desktop_bounds(displays: &[DisplayInfo]) -> Result<(i32, i32, i32, i32)> {
+    let left = displays.iter().map(|d| d.x).min();
+    let top = displays.iter().map(|d| d.y).min();
+    let right = displays.iter().map(|d| d.x + d.width as i32).max();
+    let bottom = displays.iter().map(|d| d.y + d.height as i32).max();
+    match (left, top, right, bottom) {
+        (Some(left), Some(top), Some(right), Some(bottom)) => Ok((left, top, right, bottom)),
+        _ => Err(anyhow!("No displays found")),
+    }
+}
+
+/// Converts input event coordinates to global desktop pixels
+#[derive(Debug, Clone)]
+pub struct DisplayLayout {
+    displays: Vec<DisplayInfo>,
+}
+
+impl DisplayLayout {
+    pub fn new(displays: Vec<DisplayInfo>) -> Self {
+        DisplayLayout { displays }
+    }
+
+    /// rdev reports points on macOS, which have to be scaled by the factor of the display the
+    /// cursor is on. Other platforms already report pixels.
+    pub fn to_global_pixels(&self, x: f64, y: f64) -> (f64, f64) {
+        let Some(display) = self
+            .displays
+            .iter()
+            .find(|display| display.contains_input_point(x, y))
+        else {
+            return (x, y);
+        };
+        let scale = input_scale(display.scale_factor);
+        let (left, top) = (display.x as f64 / scale, display.y as f64 / scale);
+        (
+            display.x as f64 + (x - left) * scale,
+            display.y as f64 + (y - top) * scale,
+        )
+    }
+}
+
+fn input_scale(scale_factor: f64) -> f64 {
+    if cfg!(target_os = "macos") {
+        scale_factor
+    } else {
+        1.0
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::io::ErrorKind;
+
+use serde::{Deserialize, Serialize};
+
+/// Why the recorder can't do what was asked, or why a recording ended early. Returned from the
+/// recorder commands and sent with the `recorder_error` event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")] // JSON value name
+pub enum RecorderError {
+    /// FFmpeg isn't installed and couldn't be downloaded
+    FfmpegMissing(String),
+    /// FFmpeg failed to start, or quit or hung while recording
+    FfmpegExited(String),
+    /// The OS refused screen capture or input monitoring
+    PermissionDenied(String),
+    DiskFull(String),
+    /// Input events can't be captured
+    InputHookFailed(String),
+    Network(String),
+    NotLoggedIn,
+    /// The capture settings can't be recorded with
+    InvalidConfig(String),
+    /// The command doesn't apply in the recorder's current phase
+    InvalidState(String),
+    /// The platform can't do what was asked, e.g. record a window without X11
+    Unsupported(String),
+    Other(String),
+}
+
+impl RecorderError {
+    /// Classify FFmpeg quitting on its own by the last lines it logged
+    pub fn from_ffmpeg_log(log: &[String]) -> Self {
+        let mentions = |needles: &[&str]| {
+            log.iter()
+                .any(|line| needles.iter().any(|needle| line.contains(needle)))
+        };
+        let last_line = log
+            .iter()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .cloned()
+            .unwrap_or_else(|| "FFmpeg exited unexpectedly".to_string());
+
+        if mentions(&["No space left on device"]) {
+            RecorderError::DiskFull(last_line)
+        } else if mentions(&[
+            "Permission denied",
+            "Operation not permitted",
+            "not authorized",
+            "Screen Recording",
+        ]) {
+            RecorderError::PermissionDenied(last_line)
+        } else {
+            RecorderError::FfmpegExited(last_line)
+        }
+    }
+}
+
+impl fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecorderError::FfmpegMissing(message) => {
+                write!(f, "FFmpeg is not available: {}", message)
+            }
+            RecorderError::FfmpegExited(message) => write!(f, "FFmpeg stopped: {}", message),
+            RecorderError::PermissionDenied(message) => {
+                write!(f, "Permission to record was denied: {}", message)
+            }
+            RecorderError::DiskFull(message) => write!(f, "The disk is full: {}", message),
+            RecorderError::InputHookFailed(message) => {
+                write!(f, "Failed to capture input events: {}", message)
+            }
+            RecorderError::Network(message) => write!(f, "Network error: {}", message),
+            RecorderError::NotLoggedIn => write!(f, "Not logged in, log in before recording"),
+            RecorderError::InvalidConfig(message) => {
+                write!(f, "Invalid capture settings: {}", message)
+            }
+            RecorderError::Unsupported(message) => {
+                write!(f, "Not supported on this platform: {}", message)
+            }
+            RecorderError::InvalidState(message) | RecorderError::Other(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecorderError {}
+
+/// Sort an internal error into the kind the frontend can act on, by its causes
+impl From<anyhow::Error> for RecorderError {
+    fn from(error: anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<RecorderError>() {
+            return error.clone();
+        }
+        let message = format!("{:#}", error);
+        for cause in error.chain() {
+            if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+                match io_error.kind() {
+                    ErrorKind::StorageFull => return RecorderError::DiskFull(message),
+                    ErrorKind::PermissionDenied => return RecorderError::PermissionDenied(message),
+                    _ => {}
+                }
+            }
+            if cause.is::<reqwest::Error>() {
+                return RecorderError::Network(message);
+            }
+        }
+        RecorderError::Other(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn log(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn ffmpeg_log_disk_full() {
+        let log = log(&[
+            "frame=  120 fps= 30",
+            "av_interleaved_write_frame(): No space left on device",
+            "Conversion failed!",
+        ]);
+        assert_eq!(
+            RecorderError::from_ffmpeg_log(&log),
+            RecorderError::DiskFull("Conversion failed!".to_string())
+        );
+    }
+
+    #[test]
+    fn ffmpeg_log_permission_denied() {
+        for line in [
+            "chunk_00001.mkv: Permission denied",
+            "[x11grab] Operation not permitted",
+            "[AVFoundation indev] Failed to create capture session, not authorized",
+            "Screen Recording permission is required",
+        ] {
+            assert_eq!(
+                RecorderError::from_ffmpeg_log(&log(&[line, ""])),
+                RecorderError::PermissionDenied(line.to_string()),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn ffmpeg_log_other_failure() {
+        let log = log(&["Input #0, x11grab", ":0.0: Invalid argument", "  "]);
+        assert_eq!(
+            RecorderError::from_ffmpeg_log(&log),
+            RecorderError::FfmpegExited(":0.0: Invalid argument".to_string())
+        );
+        assert_eq!(
+            RecorderError::from_ffmpeg_log(&[]),
+            RecorderError::FfmpegExited("FFmpeg exited unexpectedly".to_string())
+        );
+    }
+
+    #[test]
+    fn io_errors_by_kind() {
+        for (kind, expected) in [
+            (
+                ErrorKind::StorageFull,
+                RecorderError::DiskFull("Failed to write: disk".to_string()),
+            ),
+            (
+                ErrorKind::PermissionDenied,
+                RecorderError::PermissionDenied("Failed to write: disk".to_string()),
+            ),
+            (
+                ErrorKind::NotFound,
+                RecorderError::Other("Failed to write: disk".to_string()),
+            ),
+        ] {
+            let error = Err::<(), _>(std::io::Error::new(kind, "disk"))
+                .context("Failed to write")
+                .unwrap_err();
+            assert_eq!(RecorderError::from(error), expected, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn recorder_errors_pass_through() {
+        let error = anyhow::Error::from(RecorderError::NotLoggedIn).context("Failed to start");
+        assert_eq!(RecorderError::from(error), RecorderError::NotLoggedIn);
+    }
+
+    #[test]
+    fn request_errors_are_network_errors() {
+        let error = reqwest::Client::new().get("not a url").build().unwrap_err();
+        assert!(matches!(
+            RecorderError::from(anyhow::Error::from(error)),
+            RecorderError::Network(_)
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::files::write_atomic;
+
+/// Events of a session, one JSON object per line
+pub const JOURNAL_FILE: &str = "events.jsonl";
+/// Byte offset into the journal up to which events were accepted by the server
+pub const CURSOR_FILE: &str = "events.cursor";
+
+/// When journal writes are forced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// After every event. Survives power loss, but costs a disk flush per event.
+    Always,
+    /// At most once per interval
+    Interval { millis: u64 },
+    /// Never, writes survive the app crashing but not the machine
+    Never,
+}
+
+impl Default for FsyncPolicy {
+    fn default() -> Self {
+        FsyncPolicy::Interval { millis: 1000 }
+    }
+}
+
+/// Append-only write-ahead log of a session's events in its output directory. Every event is
+/// handed to the OS as it is appended, only forcing it to disk follows the fsync policy.
+#[derive(Debug)]
+pub struct EventJournal {
+    file: File,
+    policy: FsyncPolicy,
+    synced_at: Instant,
+}
+
+impl EventJournal {
+    /// Open the journal in `dir`, appending to events that are already in it
+    pub fn open(dir: &Path, policy: FsyncPolicy) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .context("Failed to open event journal")?;
+        Ok(EventJournal {
+            file,
+            policy,
+            synced_at: Instant::now(),
+        })
+    }
+
+    pub fn append<T: Serialize>(&mut self, event: &T) -> Result<()> {
+        let mut line = serde_json::to_vec(event).context("Failed to serialize event")?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .context("Failed to write event journal")?;
+
+        let sync = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval { millis } => {
+                self.synced_at.elapsed() >= Duration::from_millis(millis)
+            }
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Force every appended event to disk, regardless of the fsync policy
+    pub fn sync(&mut self) -> Result<()> {
+        self.file
+            .sync_data()
+            .context("Failed to sync event journal")?;
+        self.synced_at = Instant::now();
+        Ok(())
+    }
+}
+
+/// A run of journaled events and the cursor to store once they are uploaded
+pub struct JournalBatch<T> {
+    pub events: Vec<T>,
+    pub end_offset: u64,
+}
+
+/// Read up to `max_events` complete events after the upload cursor. A line that is still being
+/// written (or was cut off by a crash) ends the batch. Lines that don't parse are skipped.
+pub fn read_batch<T: DeserializeOwned>(dir: &Path, max_events: usize) -> Result<JournalBatch<T>> {
+    let offset = read_cursor(dir)?;
+    let mut file = match File::open(dir.join(JOURNAL_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(JournalBatch {
+                events: Vec::new(),
+                end_offset: offset,
+            })
+        }
+        Err(e) => return Err(e).context("Failed to open event journal"),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(file);
+
+    let mut events = Vec::new();
+    let mut end_offset = offset;
+    let mut line = String::new();
+    while events.len() < max_events {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        end_offset += read as u64;
+        match serde_json::from_str(&line) {
+            Ok(event) => events.push(event),
+            Err(e) => log::warn!("Skipping corrupt journal entry at {}: {}", end_offset, e),
+        }
+    }
+
+    Ok(JournalBatch { events, end_offset })
+}
+
+pub fn read_cursor(dir: &Path) -> Result<u64> {
+    match fs::read_to_string(dir.join(CURSOR_FILE)) {
+        Ok(cursor) => cursor.trim().parse().context("Invalid journal cursor"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e).context("Failed to read journal cursor"),
+    }
+}
+
+/// Replace the cursor atomically, so a crash leaves either the old or the new offset
+pub fn write_cursor(dir: &Path, offset: u64) -> Result<()> {
+    write_atomic(&dir.join(CURSOR_FILE), offset.to_string().as_bytes(), false)
+        .context("Failed to write journal cursor")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        n: u32,
+    }
+
+    fn journal_of(dir: &Path, events: impl IntoIterator<Item = u32>) {
+        let mut journal = EventJournal::open(dir, FsyncPolicy::Never).unwrap();
+        for n in events {
+            journal.append(&Event { n }).unwrap();
+        }
+    }
+
+    fn numbers(batch: &JournalBatch<Event>) -> Vec<u32> {
+        batch.events.iter().map(|event| event.n).collect()
+    }
+
+    fn append_raw(dir: &Path, contents: &str) {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn reads_batches_of_events() {
+        let dir = tempfile::tempdir().unwrap();
+        journal_of(dir.path(), 0..5);
+
+        let batch = read_batch::<Event>(dir.path(), 3).unwrap();
+        assert_eq!(numbers(&batch), [0, 1, 2]);
+        assert_eq!(batch.end_offset, "{\"n\":0}\n".len() as u64 * 3);
+
+        let batch = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert_eq!(numbers(&batch), [0, 1, 2, 3, 4]);
+        let journal_len = fs::metadata(dir.path().join(JOURNAL_FILE)).unwrap().len();
+        assert_eq!(batch.end_offset, journal_len);
+    }
+
+    #[test]
+    fn resumes_from_the_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        journal_of(dir.path(), 0..5);
+
+        let batch = read_batch::<Event>(dir.path(), 2).unwrap();
+        write_cursor(dir.path(), batch.end_offset).unwrap();
+        assert_eq!(read_cursor(dir.path()).unwrap(), batch.end_offset);
+
+        let rest = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert_eq!(numbers(&rest), [2, 3, 4]);
+        write_cursor(dir.path(), rest.end_offset).unwrap();
+
+        // Reopening appends after the events that are already uploaded
+        journal_of(dir.path(), [5]);
+        let batch = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert_eq!(numbers(&batch), [5]);
+    }
+
+    #[test]
+    fn torn_trailing_line_ends_the_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        journal_of(dir.path(), 0..2);
+        let complete = fs::metadata(dir.path().join(JOURNAL_FILE)).unwrap().len();
+        append_raw(dir.path(), "{\"n\":");
+
+        let batch = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert_eq!(numbers(&batch), [0, 1]);
+        assert_eq!(batch.end_offset, complete);
+        write_cursor(dir.path(), batch.end_offset).unwrap();
+
+        // The line is picked up once it is finished
+        append_raw(dir.path(), "2}\n");
+        let batch = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert_eq!(numbers(&batch), [2]);
+    }
+
+    #[test]
+    fn skips_corrupt_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        journal_of(dir.path(), [0]);
+        append_raw(dir.path(), "not json\n");
+        journal_of(dir.path(), [1]);
+
+        let batch = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert_eq!(numbers(&batch), [0, 1]);
+        let journal_len = fs::metadata(dir.path().join(JOURNAL_FILE)).unwrap().len();
+        assert_eq!(batch.end_offset, journal_len);
+    }
+
+    #[test]
+    fn missing_journal_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_cursor(dir.path()).unwrap(), 0);
+        let batch = read_batch::<Event>(dir.path(), 100).unwrap();
+        assert!(batch.events.is_empty());
+        assert_eq!(batch.end_offset, 0);
+    }
+
+    #[test]
+    fn invalid_cursor_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(CURSOR_FILE), "12ab").unwrap();
+        assert!(read_cursor(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::process::Command;
+
+use log::warn;
+
+/// Identifier of the active keyboard layout, e.g. `us` or `de(nodeadkeys)` on Linux,
+/// `com.apple.keylayout.German` on macOS and `0407:00000407` on Windows
+pub fn keyboard_layout() -> Option<String> {
+    let layout = query_keyboard_layout();
+    if layout.is_none() {
+        warn!("Could not determine the keyboard layout");
+    }
+    layout
+}
+
+#[cfg(target_os = "linux")]
+fn query_keyboard_layout() -> Option<String> {
+    let output = Command::new("setxkbmap").arg("-query").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let field = |name: &str| {
+        stdout.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name).then(|| value.trim().to_string())
+        })
+    };
+
+    let layout = field("layout")?;
+    match field("variant") {
+        Some(variant) if !variant.is_empty() => Some(format!("{}({})", layout, variant)),
+        _ => Some(layout),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn query_keyboard_layout() -> Option<String> {
+    let output = Command::new("defaults")
+        .args([
+            "read",
+            "com.apple.HIToolbox",
+            "AppleCurrentKeyboardLayoutInputSourceID",
+        ])
+        .output()
+        .ok()?;
+    let layout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!layout.is_empty()).then_some(layout)
+}
+
+#[cfg(target_os = "windows")]
+fn query_keyboard_layout() -> Option<String> {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::GetKeyboardLayout;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GetForegroundWindow, GetWindowThreadProcessId,
+    };
+
+    // Each thread has its own layout, the one typed with is the foreground window's. Without
+    // a foreground window thread 0 gets this thread's layout.
+    let layout = unsafe {
+        let thread_id = GetWindowThreadProcessId(GetForegroundWindow(), std::ptr::null_mut());
+        GetKeyboardLayout(thread_id) as usize
+    };
+    if layout == 0 {
+        return None;
+    }
+    // Language in the low word, layout in the high word, e.g. 0x04070407 for German
+    let language = layout & 0xFFFF;
+    let device = (layout >> 16) & 0xFFFF;
+    Some(format!("{:04X}:{:08X}", language, device))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn query_keyboard_layout() -> Option<String> {
+    None
+}
@@ -1,4 +1,25 @@
+mod capture;
+mod context;
+mod display;
+mod error;
+mod journal;
+mod layout;
 pub mod recording;
+mod recovery;
+mod segments;
+mod status;
+mod trajectory;
+mod upload_queue;
+mod watchdog;
 
+pub use capture::CaptureConfig;
+pub use recording::get_recorder_status;
+pub use recording::list_displays;
+pub use recording::list_windows;
+pub use recording::pause_recording;
+pub use recording::resume_recording;
+pub use recording::set_capture_target;
+pub use recording::set_fsync_policy;
+pub use recording::set_trajectory_sampling;
 pub use recording::start_recording;
 pub use recording::stop_recording;
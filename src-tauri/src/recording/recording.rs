@@ -1,9 +1,12 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result};
 use chrono::Utc;
@@ -14,14 +17,35 @@ use ffmpeg_sidecar::{
     event::FfmpegEvent,
 };
 use log::{debug, error, info, warn};
-use rdev::{listen, Event, EventType};
+use rdev::{listen, Button, Event, EventType, Key};
 use serde::{Deserialize, Serialize};
 use tauri::async_runtime::TokioRuntime;
 use tauri::{AppHandle, Emitter, Manager, State, WebviewWindow};
 use uuid::Uuid;
 
-use crate::types::{KeyboardAction, KeyboardActionKey, MouseAction, ScrollAction};
-use crate::BASE_URL;
+use super::capture::{CaptureArgs, CaptureConfig, Container, Platform};
+use super::context::{default_provider, focus_change, WindowWatcher};
+use super::display::{
+    self, display_area, region_area, CaptureArea, CaptureTarget, CoordinateTransform, DisplayInfo,
+    DisplayLayout,
+};
+use super::error::RecorderError;
+use super::journal::{self, EventJournal, FsyncPolicy};
+use super::layout::keyboard_layout;
+use super::recovery::{self, EVENTS_UPLOADED_FILE, STOPPED_FILE};
+use super::segments::{self, segment_list_file, timestamps_file};
+use super::status::{RecorderPhase, RecorderStatus, StatusTracker};
+use super::trajectory::{TrajectorySampler, TrajectorySampling};
+use super::upload_queue::{ChunkUpload, UploadQueue};
+use super::watchdog::{FfmpegWatchdog, RestartPolicy, RunFailure, RESTART_DELAY};
+use crate::api::ApiClient;
+use crate::auth::valid_jwt;
+use crate::settings::SettingsState;
+use crate::types::{
+    ButtonState, DragAction, FocusAction, KeyChord, KeySide, KeyboardAction, KeyboardActionKey,
+    Modifiers, MouseAction, MouseMoveAction, PathSample, RecordingAction, ScrollAction,
+    WindowContext,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct RecordingEvent {
@@ -31,139 +55,631 @@ struct RecordingEvent {
     mouse_y: f64,
 }
 
+/// A batch of journaled events. A batch can reach the server twice: the journal cursor is
+/// only advanced once the server accepted it, so a crash in between sends it again, possibly
+/// with more events after it. The server drops events whose `sequence` it already stored for
+/// the session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeventRequestWrapper {
+    pub session: SessionInfo,
     pub events: Vec<DeventRequest>,
 }
 
+/// Metadata that applies to every event of a session, written to `session.json` in the session
+/// directory and sent along with the events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub session_id: Uuid,
+    pub started_at_nanos: i64,
+    pub keyboard_layout: Option<String>,
+    /// Every connected display, event coordinates are global desktop pixels across all of them
+    pub displays: Vec<DisplayInfo>,
+    pub capture_target: CaptureTarget,
+    /// Pointer events outside of this area are not recorded
+    pub capture_area: CaptureArea,
+    /// Maps event coordinates to pixels in the recorded video
+    pub video_transform: CoordinateTransform,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeventRequest {
     pub session_id: Uuid,
     pub mouse_action: Option<MouseAction>,
+    pub mouse_button_state: Option<ButtonState>,
+    pub drag_action: Option<DragAction>,
+    pub mouse_move_action: Option<MouseMoveAction>,
     pub keyboard_action: Option<KeyboardAction>,
     pub scroll_action: Option<ScrollAction>,
+    pub focus_action: Option<FocusAction>,
+    pub recording_action: Option<RecordingAction>,
+    pub modifiers: Modifiers,
+    pub window: Option<WindowContext>,
     pub mouse_x: i32,
     pub mouse_y: i32,
     pub event_timestamp_nanos: i64,
+    /// Position of the event in the session's journal, unique within the session and
+    /// increasing in journal order. Identifies events that are uploaded twice.
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+impl DeventRequest {
+    fn new(
+        session_id: Uuid,
+        context: EventContext,
+        mouse_pos: (i32, i32),
+        event_timestamp_nanos: i64,
+    ) -> Self {
+        DeventRequest {
+            session_id,
+            mouse_action: None,
+            mouse_button_state: None,
+            drag_action: None,
+            mouse_move_action: None,
+            keyboard_action: None,
+            scroll_action: None,
+            focus_action: None,
+            recording_action: None,
+            modifiers: context.modifiers,
+            window: context.window,
+            mouse_x: mouse_pos.0,
+            mouse_y: mouse_pos.1,
+            event_timestamp_nanos,
+            sequence: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SaveRecordingRequest {
+    /// Stays the same across retries of a chunk, so the server can deduplicate them
     pub recording_id: Uuid,
     pub session_id: Uuid,
+    pub chunk_index: u32,
     pub start_timestamp_nanos: i64,
     pub duration_ms: u64,
 }
 
+/// Input state an event happened in, captured when the event (or gesture) started
+#[derive(Debug, Clone, Default)]
+struct EventContext {
+    modifiers: Modifiers,
+    window: Option<WindowContext>,
+}
+
+/// A key that is currently held down, waiting for its release to compute the hold duration
+#[derive(Debug, Clone)]
+struct HeldKey {
+    pressed_at_nanos: i64,
+    context: EventContext,
+    text: Option<String>,
+    mouse_x: i32,
+    mouse_y: i32,
+}
+
+/// Most events sent to the server in one request
+const EVENT_BATCH_SIZE: usize = 500;
+/// How often journaled events are streamed to the server while recording
+const EVENT_UPLOAD_INTERVAL: Duration = Duration::from_secs(5);
+/// Most events held back while a key or button is down. A key held longer than that (e.g. one
+/// whose release was missed) lets the journal get out of order rather than grow the buffer
+/// forever.
+const MAX_PENDING_EVENTS: usize = 10_000;
+
+/// Minimum distance in pixels the cursor has to travel while a button is held to count as a drag
+const DRAG_THRESHOLD_PX: i32 = 5;
+
+/// A mouse button that is currently held down, collecting the cursor path for drag detection
+#[derive(Debug, Clone)]
+struct HeldButton {
+    pressed_at_nanos: i64,
+    context: EventContext,
+    start: (i32, i32),
+    path: Vec<PathSample>,
+}
+
+impl HeldButton {
+    fn is_drag(&self, end: (i32, i32)) -> bool {
+        (end.0 - self.start.0)
+            .abs()
+            .max((end.1 - self.start.1).abs())
+            >= DRAG_THRESHOLD_PX
+    }
+}
+
 #[derive(Debug)]
 struct RecordingSession {
     id: Uuid,
-    events: Vec<DeventRequest>,
+    info: SessionInfo,
+    journal: EventJournal,
+    held_keys: HashMap<Key, HeldKey>,
+    held_buttons: HashMap<Button, HeldButton>,
+    window: Option<WindowContext>,
+    /// Cursor position at the last input event
+    mouse_pos: (i32, i32),
+    trajectory: TrajectorySampler,
     output_dir: PathBuf,
+    /// Journaled events, reported in the recorder status
+    event_count: Arc<AtomicU64>,
+    /// `sequence` of the next journaled event
+    next_sequence: u64,
+    /// Events waiting for the held keys and buttons to be released. Key events and drags are
+    /// stamped with the press but only known at the release, so the events in between wait to
+    /// be journaled in timestamp order.
+    pending: Vec<DeventRequest>,
+    app_handle: Arc<AppHandle>,
+    /// Set once a failed journal write ended the recording, so it is reported once
+    write_failed: bool,
+    /// FFmpeg died and the `gap` marker isn't followed by `resumed` yet
+    in_video_gap: bool,
 }
 
 impl RecordingSession {
-    fn new(app_handle: Arc<AppHandle>) -> Result<Self> {
+    fn new(
+        app_handle: Arc<AppHandle>,
+        trajectory_sampling: TrajectorySampling,
+        fsync_policy: FsyncPolicy,
+        displays: Vec<DisplayInfo>,
+        capture_target: CaptureTarget,
+        capture_args: &CaptureArgs,
+        event_count: Arc<AtomicU64>,
+    ) -> Result<Self> {
         // Store the recording session in a unique directory under app data (different but
-        // predictable per OS, we should always have read/write access)
+        // predictable per OS, we should always have read/write access). The id keeps a session
+        // started within the same second as the last one out of its directory.
         let id = Uuid::new_v4();
         let timestamp = Utc::now().format("%Y%m%d_%H%M%S").to_string();
-        let output_dir = app_handle
+        let output_root = app_handle
             .path()
             .app_data_dir()
-            .unwrap()
-            .join(format!("output/{}", timestamp));
-        fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
+            .context("Failed to find the app data directory")?
+            .join("output");
+        fs::create_dir_all(&output_root).context("Failed to create output directory")?;
+        let output_dir = output_root.join(format!("{}_{}", timestamp, id));
+        fs::create_dir(&output_dir).context("Failed to create session directory")?;
 
         let recordings_dir = output_dir.join("recordings");
         fs::create_dir_all(&recordings_dir).context("Failed to create recordings directory")?;
 
+        let info = SessionInfo {
+            session_id: id,
+            started_at_nanos: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            keyboard_layout: keyboard_layout(),
+            displays,
+            capture_target,
+            video_transform: capture_args.video_transform(),
+            capture_area: capture_args.area().clone(),
+        };
+        let info_file = fs::File::create(output_dir.join("session.json"))
+            .context("Failed to create session info file")?;
+        serde_json::to_writer_pretty(info_file, &info).context("Failed to write session info")?;
+        let journal = EventJournal::open(&output_dir, fsync_policy)?;
+
         Ok(RecordingSession {
             id,
-            events: Vec::new(),
+            info,
+            journal,
+            held_keys: HashMap::new(),
+            held_buttons: HashMap::new(),
+            window: None,
+            mouse_pos: (0, 0),
+            trajectory: TrajectorySampler::new(trajectory_sampling),
             output_dir,
+            event_count,
+            next_sequence: 0,
+            pending: Vec::new(),
+            app_handle,
+            write_failed: false,
+            in_video_gap: false,
         })
     }
 
-    fn video_path(&self) -> PathBuf {
-        self.output_dir.join("recordings")
+    /// Start tracking a key press. Auto-repeat presses of a key that is already held only add
+    /// their text, so the duration is measured from the first press.
+    fn press_key(&mut self, key: Key, text: Option<String>, timestamp: i64, mouse_pos: (i32, i32)) {
+        if let Some(held) = self.held_keys.get_mut(&key) {
+            if let Some(text) = text {
+                held.text.get_or_insert_with(String::new).push_str(&text);
+            }
+            return;
+        }
+
+        let context = self.context();
+        self.held_keys.insert(
+            key,
+            HeldKey {
+                pressed_at_nanos: timestamp,
+                context,
+                text,
+                mouse_x: mouse_pos.0,
+                mouse_y: mouse_pos.1,
+            },
+        );
+    }
+
+    /// Record a keyboard action for a released key. Releases of keys that were pressed before the
+    /// recording started are dropped, we can't know how long they were held.
+    fn release_key(&mut self, key: Key, timestamp: i64) {
+        if let Some(held) = self.held_keys.remove(&key) {
+            self.push_key_event(key, held, timestamp);
+        }
+        self.write_pending_when_idle();
+    }
+
+    /// Release every key that is still held, used when the recording stops mid-press
+    fn release_all_keys(&mut self, timestamp: i64) {
+        let mut held_keys: Vec<(Key, HeldKey)> = self.held_keys.drain().collect();
+        held_keys.sort_by_key(|(_, held)| held.pressed_at_nanos);
+        for (key, held) in held_keys {
+            self.push_key_event(key, held, timestamp);
+        }
+        self.write_pending_when_idle();
+    }
+
+    fn push_key_event(&mut self, key: Key, held: HeldKey, released_at_nanos: i64) {
+        let duration_ms = (released_at_nanos - held.pressed_at_nanos).max(0) / 1_000_000;
+        let keyboard_action_key: KeyboardActionKey = key.into();
+
+        // The event is stamped with the press time, so it lines up with the video frame where the
+        // key went down
+        let chord = KeyChord::new(held.context.modifiers, key);
+        let mut devent_request = DeventRequest::new(
+            self.id,
+            held.context,
+            (held.mouse_x, held.mouse_y),
+            held.pressed_at_nanos,
+        );
+        devent_request.keyboard_action = Some(KeyboardAction {
+            key: keyboard_action_key,
+            side: KeySide::of(key),
+            duration: duration_ms.min(i32::MAX as i64) as i32,
+            chord,
+            text: held.text,
+        });
+        self.push_event(devent_request);
+    }
+
+    /// Modifier keys currently held down. Keys pressed before the recording started are unknown.
+    fn modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::NONE;
+        for key in self.held_keys.keys() {
+            if let Some(modifier) = Modifiers::from_key(*key) {
+                modifiers.insert(modifier);
+            }
+        }
+        modifiers
+    }
+
+    fn context(&self) -> EventContext {
+        EventContext {
+            modifiers: self.modifiers(),
+            window: self.window.clone(),
+        }
+    }
+
+    /// Update the focused window, recording a focus action when focus moved to another window
+    fn set_window(&mut self, window: Option<WindowContext>, timestamp: i64, mouse_pos: (i32, i32)) {
+        let Some(focus_action) = focus_change(&self.window, &window) else {
+            // Same window, but the title or bounds may have changed
+            self.window = window;
+            return;
+        };
+
+        // Motion so far happened in the previous window
+        self.flush_trajectory();
+
+        self.window = window;
+        let mut devent_request = DeventRequest::new(self.id, self.context(), mouse_pos, timestamp);
+        devent_request.focus_action = Some(focus_action);
+        self.push_event(devent_request);
+    }
+
+    fn press_button(&mut self, button: Button, timestamp: i64, mouse_pos: (i32, i32)) {
+        let context = self.context();
+        let mut devent_request = DeventRequest::new(self.id, context.clone(), mouse_pos, timestamp);
+        devent_request.mouse_action = Some(button.into());
+        devent_request.mouse_button_state = Some(ButtonState::Pressed);
+        self.push_event(devent_request);
+
+        self.held_buttons.insert(
+            button,
+            HeldButton {
+                pressed_at_nanos: timestamp,
+                context,
+                start: mouse_pos,
+                path: vec![PathSample {
+                    x: mouse_pos.0,
+                    y: mouse_pos.1,
+                    timestamp_nanos: timestamp,
+                }],
+            },
+        );
+    }
+
+    /// Add the cursor position to the trajectory stream and the path of every held button
+    fn move_pointer(&mut self, timestamp: i64, mouse_pos: (i32, i32)) {
+        let sample = PathSample {
+            x: mouse_pos.0,
+            y: mouse_pos.1,
+            timestamp_nanos: timestamp,
+        };
+        if let Some(path) = self.trajectory.push(sample) {
+            self.push_trajectory(path);
+        }
+
+        for held in self.held_buttons.values_mut() {
+            if let Some(last) = held.path.last() {
+                if (last.x, last.y) == mouse_pos {
+                    continue;
+                }
+            }
+            held.path.push(PathSample {
+                x: mouse_pos.0,
+                y: mouse_pos.1,
+                timestamp_nanos: timestamp,
+            });
+        }
+    }
+
+    /// Record the button release, plus a drag action if the cursor moved far enough while the
+    /// button was held
+    fn release_button(&mut self, button: Button, timestamp: i64, mouse_pos: (i32, i32)) {
+        let mut devent_request = DeventRequest::new(self.id, self.context(), mouse_pos, timestamp);
+        devent_request.mouse_action = Some(button.into());
+        devent_request.mouse_button_state = Some(ButtonState::Released);
+        self.push_event(devent_request);
+
+        if let Some(held) = self.held_buttons.remove(&button) {
+            self.push_drag(button, held, timestamp, mouse_pos);
+        }
+        self.write_pending_when_idle();
+    }
+
+    /// Record a drag action if the cursor moved far enough while the button was held
+    fn push_drag(
+        &mut self,
+        button: Button,
+        mut held: HeldButton,
+        timestamp: i64,
+        mouse_pos: (i32, i32),
+    ) {
+        if !held.is_drag(mouse_pos) {
+            return;
+        }
+        if held.path.last().map(|last| (last.x, last.y)) != Some(mouse_pos) {
+            held.path.push(PathSample {
+                x: mouse_pos.0,
+                y: mouse_pos.1,
+                timestamp_nanos: timestamp,
+            });
+        }
+
+        // Like keyboard actions, drags are stamped with the time the gesture started
+        let duration_ms = (timestamp - held.pressed_at_nanos).max(0) / 1_000_000;
+        let mut devent_request =
+            DeventRequest::new(self.id, held.context, held.start, held.pressed_at_nanos);
+        devent_request.drag_action = Some(DragAction {
+            button: button.into(),
+            start_x: held.start.0,
+            start_y: held.start.1,
+            end_x: mouse_pos.0,
+            end_y: mouse_pos.1,
+            path: held.path,
+            duration: duration_ms.min(i32::MAX as i64) as i32,
+        });
+        self.push_event(devent_request);
+    }
+
+    /// Record the cursor motion collected since the last flush, called before any other input
+    /// event so the trajectory stream stays in order
+    fn flush_trajectory(&mut self) {
+        if let Some(path) = self.trajectory.flush() {
+            self.push_trajectory(path);
+        }
+    }
+
+    /// Record a trajectory segment, split into the stretches that stay inside the capture area
+    fn push_trajectory(&mut self, path: Vec<PathSample>) {
+        let area = &self.info.capture_area;
+        let mut stretches = vec![Vec::new()];
+        for sample in path {
+            if area.contains(sample.x, sample.y) {
+                stretches.last_mut().unwrap().push(sample);
+            } else if !stretches.last().unwrap().is_empty() {
+                stretches.push(Vec::new());
+            }
+        }
+
+        for path in stretches.into_iter().filter(|path| path.len() >= 2) {
+            let start = (path[0].x, path[0].y);
+            let mut devent_request =
+                DeventRequest::new(self.id, self.context(), start, path[0].timestamp_nanos);
+            devent_request.mouse_move_action = Some(MouseMoveAction { path });
+            self.append_event(devent_request);
+        }
+    }
+
+    /// Record an event unless it happened outside of the capture target: pointer events outside
+    /// of the capture area, and for window targets keyboard input sent to other windows
+    fn push_event(&mut self, devent_request: DeventRequest) {
+        if self.is_captured(&devent_request) {
+            self.append_event(devent_request);
+        }
+    }
+
+    /// Journal an event, or hold it back while a key or button is down
+    fn append_event(&mut self, devent_request: DeventRequest) {
+        self.pending.push(devent_request);
+        self.write_pending_when_idle();
+    }
+
+    fn write_pending_when_idle(&mut self) {
+        let idle = self.held_keys.is_empty() && self.held_buttons.is_empty();
+        if idle || self.pending.len() >= MAX_PENDING_EVENTS {
+            self.write_pending();
+        }
     }
 
-    fn segment_csv_path(&self) -> PathBuf {
-        self.output_dir.join("segments.csv")
+    /// Journal the held back events in timestamp order, events with the same timestamp in the
+    /// order they happened
+    fn write_pending(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.sort_by_key(|devent_request| devent_request.event_timestamp_nanos);
+        for devent_request in pending {
+            self.journal_event(devent_request);
+        }
     }
 
-    fn timestamp_path(&self) -> PathBuf {
-        self.output_dir.join("timestamps.txt")
+    fn journal_event(&mut self, mut devent_request: DeventRequest) {
+        devent_request.sequence = self.next_sequence;
+        match self.journal.append(&devent_request) {
+            Ok(()) => {
+                self.next_sequence += 1;
+                self.event_count.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(e) => {
+                error!("Failed to journal event: {:?}", e);
+                // Nothing more can be recorded, the rest of the session would be lost
+                if !self.write_failed {
+                    self.write_failed = true;
+                    report_failure(&self.app_handle, RecorderError::from(e));
+                }
+            }
+        }
+    }
+
+    fn is_captured(&self, devent_request: &DeventRequest) -> bool {
+        let area = &self.info.capture_area;
+        let in_target_window = |window: &Option<WindowContext>| match self.info.capture_target {
+            CaptureTarget::Window { window_id } => {
+                window.as_ref().map(|window| window.window_id) == Some(window_id)
+            }
+            _ => true,
+        };
+
+        if let Some(drag) = &devent_request.drag_action {
+            area.contains(drag.start_x, drag.start_y) || area.contains(drag.end_x, drag.end_y)
+        } else if devent_request.keyboard_action.is_some() {
+            in_target_window(&devent_request.window)
+        } else if let Some(focus) = &devent_request.focus_action {
+            in_target_window(&devent_request.window) || in_target_window(&focus.previous)
+        } else {
+            area.contains(devent_request.mouse_x, devent_request.mouse_y)
+        }
+    }
+
+    /// End every gesture in progress and mark the start of the pause. Keys and buttons held
+    /// across the pause are cut off, their release would end up on the other side of the gap.
+    fn pause(&mut self, timestamp: i64) {
+        self.flush_trajectory();
+        self.release_all_keys(timestamp);
+        self.held_buttons.clear();
+        self.push_marker(RecordingAction::Paused, timestamp);
+    }
+
+    fn resume(&mut self, timestamp: i64) {
+        self.in_video_gap = false;
+        self.push_marker(RecordingAction::Resumed, timestamp);
+    }
+
+    /// Mark where the video stopped after FFmpeg died. Input events are still recorded.
+    fn start_video_gap(&mut self, timestamp: i64) {
+        if !self.in_video_gap {
+            self.in_video_gap = true;
+            self.push_marker(RecordingAction::Gap, timestamp);
+        }
+    }
+
+    /// Close a gap once FFmpeg runs again
+    fn end_video_gap(&mut self, timestamp: i64) {
+        if self.in_video_gap {
+            self.resume(timestamp);
+        }
+    }
+
+    /// Record a marker, which applies to the session rather than the capture area
+    fn push_marker(&mut self, action: RecordingAction, timestamp: i64) {
+        let mut devent_request =
+            DeventRequest::new(self.id, self.context(), self.mouse_pos, timestamp);
+        devent_request.recording_action = Some(action);
+        self.append_event(devent_request);
     }
 }
 
-fn get_ffmpeg_command(
-    video_output_path: &str,
-    segment_csv_path: &str,
-    timestamp_path: &str,
-) -> FfmpegCommand {
+/// FFmpeg for one capture run
+fn get_ffmpeg_command(capture_args: &CaptureArgs) -> FfmpegCommand {
     let mut cmd = FfmpegCommand::new();
+    cmd.args(capture_args.build());
+    debug!("COMMAND: {:?}", cmd);
+    cmd
+}
 
-    // OS-specific input configuration
-    #[cfg(target_os = "macos")]
-    {
-        let capture_device = get_ffmpeg_capture_device();
-        // if macos, must get ffmpeg device first.
-        cmd.args(["-f", "avfoundation"])
-            .args(["-capture_cursor", "1"])
-            // .args(["-capture_mouse_clicks", "1"])
-            .args(["-i", &format!("{}:none", capture_device)]);
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        cmd.args(["-f", "gdigrab"])
-            .args(["-draw_mouse", "1"])
-            .args(["-i", "desktop"]);
-    }
-
-    #[cfg(target_os = "linux")]
-    {
-        cmd.args(["-f", "x11grab"])
-            .args(["-draw_mouse", "1"])
-            .args(["-i", ":0.0"]);
-    }
-
-    // Common configuration for all platforms
-    cmd.args(["-framerate", "30"])
-        .args(["-vcodec", "libx264"])
-        .args(["-preset", "ultrafast"])
-        .args(["-crf", "23"])
-        .args([
-            "-filter_complex",
-            "settb=1/1000,setpts='RTCTIME/1000',mpdecimate,split=2[out][ts]",
-        ])
-        .args(["-map", "[out]"])
-        .args(["-vcodec", "libx264"])
-        .args(["-pix_fmt", "yuv420p"])
-        .args(["-threads", "0"])
-        .args(["-force_key_frames", "expr:gte(t,n_forced*60)"])
-        .args(["-f", "segment"])
-        .args(["-segment_time", "15"]) // 60 seconds per chunk
-        .args(["-reset_timestamps", "1"])
-        .args(["-segment_format", "mkv"])
-        .args(["-segment_list_type", "csv"])
-        .args(["-segment_list", segment_csv_path])
-        .output(video_output_path)
-        .args(["-map", "[ts]"])
-        .args(["-f", "mkvtimestamp_v2"])
-        .arg(timestamp_path)
-        .args(["-vsync", "0"]);
+/// Resolve a capture target to the area of the desktop to record
+fn resolve_capture_area(
+    target: CaptureTarget,
+    displays: &[DisplayInfo],
+) -> Result<CaptureArea, RecorderError> {
+    let area = match target {
+        CaptureTarget::Display { selection } => display_area(selection, displays),
+        CaptureTarget::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        } => region_area(x, y, width, height, displays),
+        CaptureTarget::Window { window_id } => {
+            let mut provider = default_provider();
+            if !provider.supports_windows() {
+                return Err(RecorderError::Unsupported(
+                    "Windows can only be recorded on X11".to_string(),
+                ));
+            }
+            let window = provider.window(window_id).ok_or_else(|| {
+                RecorderError::InvalidConfig(format!("Window {} not found", window_id))
+            })?;
+            region_area(window.x, window.y, window.width, window.height, displays)
+        }
+    };
+    area.map_err(|e| RecorderError::InvalidConfig(e.to_string()))
+}
 
-    debug!("COMMAND: {:?}", cmd);
-    debug!("OUTPUT: {:?}", video_output_path);
+/// Arguments for recording `area` with `config`, the outputs are added per run
+fn capture_args(
+    config: CaptureConfig,
+    area: CaptureArea,
+    displays: &[DisplayInfo],
+) -> Result<CaptureArgs, RecorderError> {
+    let display = area
+        .displays
+        .first()
+        .and_then(|index| displays.get(*index))
+        .cloned();
+    // avfoundation numbers screens in its own order, not in the order of `displays`
+    #[cfg(target_os = "macos")]
+    let capture_device = {
+        let info = display.as_ref().ok_or_else(|| {
+            RecorderError::InvalidConfig("The capture area is not on a display".to_string())
+        })?;
+        let screen =
+            display::avfoundation_screen(info).map_err(|e| RecorderError::Other(e.to_string()))?;
+        get_ffmpeg_capture_device(screen)?
+    };
+    #[cfg(not(target_os = "macos"))]
+    let capture_device = 1;
 
-    cmd
+    let mut capture_args =
+        CaptureArgs::new(config, Platform::current(), area).capture_device(capture_device);
+    if let Ok(x11_display) = std::env::var("DISPLAY") {
+        capture_args = capture_args.x11_display(&x11_display);
+    }
+    if let Some(display) = display {
+        capture_args = capture_args.display(display);
+    }
+    Ok(capture_args)
 }
 
 // only for selecting right dev in macos avfoundation
-fn get_ffmpeg_capture_device() -> u32 {
+fn get_ffmpeg_capture_device(screen: usize) -> Result<u32, RecorderError> {
     let (format, input) = if cfg!(target_os = "windows") {
         ("gdigrab", "desktop")
     } else if cfg!(target_os = "macos") {
@@ -177,13 +693,13 @@ fn get_ffmpeg_capture_device() -> u32 {
     FfmpegCommand::new()
         .args(["-f", format, "-list_devices", "true", "-i", input])
         .spawn()
-        .expect("Failed to spawn FFmpeg")
+        .map_err(|e| RecorderError::FfmpegMissing(e.to_string()))?
         .iter()
-        .expect("Failed to get output")
+        .map_err(|e| RecorderError::FfmpegMissing(e.to_string()))?
         .for_each(|event| {
             if let FfmpegEvent::Log(_, line) = event {
-                let target_str = "Capture screen 0";
-                if line.contains(target_str) {
+                let target_str = format!("Capture screen {}", screen);
+                if line.contains(&target_str) {
                     let parts: Vec<&str> = line.split('[').collect();
                     if parts.len() >= 4 {
                         if let Some(number_str) = parts[3].split(']').next() {
@@ -197,145 +713,225 @@ fn get_ffmpeg_capture_device() -> u32 {
             }
         });
 
-    capture_device
+    Ok(capture_device)
 }
 
 pub struct RecorderState {
     app_handle: Arc<AppHandle>,
+    api: ApiClient,
+    /// The recorder's phase, which decides what the commands may do
+    status: Arc<StatusTracker>,
     event_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
     ffmpeg_child: Arc<Mutex<Option<FfmpegChild>>>,
     ffmpeg_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Signals for the capture threads, following the phase
     is_recording: Arc<AtomicBool>,
+    is_paused: Arc<AtomicBool>,
     runtime: Arc<TokioRuntime>,
     session: Arc<Mutex<Option<RecordingSession>>>,
+    trajectory_sampling: Arc<Mutex<TrajectorySampling>>,
+    fsync_policy: Arc<Mutex<FsyncPolicy>>,
+    journal_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    segments_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Uploads the chunks of the current session, returns whether all of them went through
+    upload_handle: Arc<Mutex<Option<JoinHandle<Result<()>>>>>,
+    capture_target: Arc<Mutex<CaptureTarget>>,
 }
 
 impl RecorderState {
     pub fn new(app_handle: &AppHandle) -> Self {
+        let app_handle = Arc::new(app_handle.clone());
         RecorderState {
-            app_handle: Arc::new(app_handle.clone()),
+            status: Arc::new(StatusTracker::new(app_handle.clone())),
+            api: ApiClient::new((*app_handle).clone()),
+            app_handle,
             event_handle: Arc::new(Mutex::new(None)),
             ffmpeg_child: Arc::new(Mutex::new(None)),
             ffmpeg_handle: Arc::new(Mutex::new(None)),
             is_recording: Arc::new(AtomicBool::new(false)),
+            is_paused: Arc::new(AtomicBool::new(false)),
             runtime: Arc::new(TokioRuntime::new().expect("Failed to create Tokio runtime")),
             session: Arc::new(Mutex::new(None)),
+            trajectory_sampling: Arc::new(Mutex::new(TrajectorySampling::default())),
+            fsync_policy: Arc::new(Mutex::new(FsyncPolicy::default())),
+            journal_handle: Arc::new(Mutex::new(None)),
+            segments_handle: Arc::new(Mutex::new(None)),
+            upload_handle: Arc::new(Mutex::new(None)),
+            capture_target: Arc::new(Mutex::new(CaptureTarget::default())),
         }
     }
 
-    fn start_recording(&self) -> Result<()> {
-        let mut session_guard = self.session.lock().unwrap();
-        if session_guard.is_some() {
-            return Err(anyhow!("Recording is already in progress"));
+    /// Finish the sessions a previous run left behind (crashed, killed or offline at the end) in
+    /// the background
+    pub fn recover_sessions(&self) -> Result<()> {
+        let output_root = self.app_handle.path().app_data_dir()?.join("output");
+        let sessions = recovery::unfinished_sessions(&output_root);
+        if sessions.is_empty() {
+            return Ok(());
+        }
+
+        info!("Found {} unfinished sessions", sessions.len());
+        let api = self.api.clone();
+        let runtime = self.runtime.clone();
+        let status = self.status.clone();
+        thread::spawn(move || {
+            // Needed to repair the video of crashed sessions
+            auto_download().unwrap_or_else(|e| error!("Failed to download ffmpeg: {:?}", e));
+            for session_dir in sessions {
+                if let Err(e) = recovery::recover_session(&session_dir, &api, &runtime, &status) {
+                    warn!(
+                        "Failed to recover {:?}, retrying on next start: {:?}",
+                        session_dir, e
+                    );
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn main_window(&self) -> Result<WebviewWindow> {
+        self.app_handle
+            .get_webview_window("main")
+            .ok_or_else(|| anyhow!("The main window is closed"))
+    }
+
+    fn list_displays(&self) -> Result<Vec<DisplayInfo>> {
+        display::list_displays(&self.main_window()?)
+    }
+
+    /// Windows that can be recorded with `CaptureTarget::Window`. Fails where windows can't be
+    /// recorded, so the UI doesn't offer the option.
+    fn list_windows(&self) -> Result<Vec<WindowContext>, RecorderError> {
+        let mut provider = default_provider();
+        if !provider.supports_windows() {
+            return Err(RecorderError::Unsupported(
+                "Windows can only be recorded on X11".to_string(),
+            ));
         }
-        let new_session = RecordingSession::new(self.app_handle.clone())?;
+        Ok(provider.windows())
+    }
+
+    /// Takes effect from the next recording session
+    fn set_capture_target(&self, target: CaptureTarget) -> Result<(), RecorderError> {
+        // Fail early instead of when the next recording starts
+        resolve_capture_area(target, &self.list_displays()?)?;
+        *self.capture_target.lock().unwrap() = target;
+        Ok(())
+    }
+
+    /// Takes effect from the next recording session
+    fn set_trajectory_sampling(&self, sampling: TrajectorySampling) {
+        *self.trajectory_sampling.lock().unwrap() = sampling;
+    }
+
+    /// Takes effect from the next recording session
+    fn set_fsync_policy(&self, policy: FsyncPolicy) {
+        *self.fsync_policy.lock().unwrap() = policy;
+    }
+
+    fn status(&self) -> RecorderStatus {
+        self.status.status()
+    }
+
+    fn start_recording(&self) -> Result<(), RecorderError> {
+        // Nothing recorded without a token could be uploaded
+        if valid_jwt(&self.app_handle).is_none() {
+            return Err(RecorderError::NotLoggedIn);
+        }
+        if self.status.transition(RecorderPhase::Starting).is_err() {
+            return Err(RecorderError::InvalidState(
+                "Recording is already in progress".to_string(),
+            ));
+        }
+        match self.start_session() {
+            Ok(()) => {
+                self.status.transition(RecorderPhase::Recording)?;
+                Ok(())
+            }
+            Err(e) => {
+                let error = RecorderError::from(e);
+                self.emit_error(&error);
+                self.status.fail(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    /// Tell the UI why the recorder went to `Error`
+    fn emit_error(&self, error: &RecorderError) {
+        if let Err(e) = self.app_handle.emit("recorder_error", error) {
+            warn!("Failed to emit recorder_error: {:?}", e);
+        }
+    }
+
+    fn start_session(&self) -> Result<()> {
+        info!("Ffmpeg installed: {:?}", ffmpeg_is_installed());
+        if let Err(e) = auto_download() {
+            error!("Failed to download ffmpeg: {:?}", e);
+            if !ffmpeg_is_installed() {
+                return Err(RecorderError::FfmpegMissing(e.to_string()).into());
+            }
+        }
+
+        let capture_config = self.app_handle.state::<SettingsState>().get().capture;
+        capture_config
+            .validate()
+            .map_err(|e| RecorderError::InvalidConfig(e.to_string()))?;
+
+        let mut session_guard = self.session.lock().unwrap();
+        let main_window = self.main_window()?;
+        let displays = display::list_displays(&main_window)?;
+        let capture_target = *self.capture_target.lock().unwrap();
+        let area = resolve_capture_area(capture_target, &displays)?;
+        info!("Recording {:?} at {:?}", capture_target, area);
+        let display_layout = DisplayLayout::new(displays.clone());
+        let capture_args = capture_args(capture_config, area, &displays)?;
+
+        let trajectory_sampling = *self.trajectory_sampling.lock().unwrap();
+        let fsync_policy = *self.fsync_policy.lock().unwrap();
+        let new_session = RecordingSession::new(
+            self.app_handle.clone(),
+            trajectory_sampling,
+            fsync_policy,
+            displays.clone(),
+            capture_target,
+            &capture_args,
+            self.status.event_counter(),
+        )?;
         // TODO: use Arcs here
         let session_id = new_session.id;
-        let video_dir_path = new_session.video_path();
-        let video_dir_path_clone = video_dir_path.clone();
-        let timestamp_path = new_session.timestamp_path();
-        let segment_csv_path = new_session.segment_csv_path();
+        let output_dir = new_session.output_dir.clone();
+        let session_info = new_session.info.clone();
+        let upload_queue = Arc::new(Mutex::new(UploadQueue::load(&output_dir)?));
+        self.status.set_session(session_id);
         *session_guard = Some(new_session);
         drop(session_guard);
 
+        self.is_paused.store(false, Ordering::SeqCst);
         self.is_recording.store(true, Ordering::SeqCst);
 
         // Start FFmpeg process in a separate thread
         let is_recording = self.is_recording.clone();
+        let is_paused = self.is_paused.clone();
         let ffmpeg_child = self.ffmpeg_child.clone();
+        // Disconnected when the FFmpeg thread ends, however it ends
+        let (ffmpeg_exited_tx, ffmpeg_exited) = mpsc::channel::<()>();
 
         let ffmpeg_handle = thread::spawn({
-            let is_recording = is_recording.clone();
-            let ffmpeg_child = ffmpeg_child.clone();
-
+            let session_dir = output_dir.clone();
+            let app_handle = self.app_handle.clone();
+            let upload_queue = upload_queue.clone();
             move || {
-                let child_result = get_ffmpeg_command(
-                    video_dir_path.join("chunk_%04d.mkv").to_str().unwrap(),
-                    segment_csv_path.to_str().unwrap(),
-                    timestamp_path.to_str().unwrap(),
-                )
-                .spawn();
-
-                let mut child = match child_result {
-                    Ok(child) => child,
-                    Err(e) => {
-                        error!("Failed to start FFmpeg: {}", e);
-                        error!("Error kind: {:?}", e.kind());
-                        error!("Raw OS error: {:?}", e.raw_os_error());
-
-                        // If it's a "not found" error, it might be a PATH issue
-                        if e.kind() == std::io::ErrorKind::NotFound {
-                            error!("FFmpeg command not found. Check if FFmpeg is installed and in PATH.");
-                            if let Ok(path) = std::env::var("PATH") {
-                                error!("Current PATH: {}", path);
-                            }
-                        }
-
-                        panic!("Cannot proceed without FFmpeg");
-                    }
-                };
-
-                let stdout = child.take_stdout().expect("Failed to get stdout");
-                let stderr = child.take_stderr().expect("Failed to get stderr");
-
-                *ffmpeg_child.lock().expect("Failed to lock ffmpeg_child") = Some(child);
-
-                let stdout_handle = thread::spawn(move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines() {
-                        if let Ok(line) = line {
-                            debug!("[ffmpeg stdout] {}", line);
-                        }
-                    }
-                });
-
-                let stderr_handle = thread::spawn(move || {
-                    let reader = BufReader::new(stderr);
-                    for line in reader.lines() {
-                        if let Ok(line) = line {
-                            debug!("[ffmpeg stderr] {}", line);
-                        }
-                    }
-                });
-
-                info!("Recording started and waiting for stop signal");
-                while is_recording.load(Ordering::SeqCst) {
-                    thread::sleep(Duration::from_millis(100));
-                }
-
-                // Gracefully stop FFmpeg
-                if let Some(mut child) = ffmpeg_child
-                    .lock()
-                    .expect("Failed to lock ffmpeg_child")
-                    .take()
-                {
-                    info!("Stopping FFmpeg");
-                    match child.quit() {
-                        Ok(_) => {
-                            match child.wait() {
-                                Ok(exit_status) => info!("FFmpeg stopped with {:?}", exit_status),
-                                Err(e) => {
-                                    error!("Failed to stop FFmpeg: {:?}", e);
-                                    warn!("Force killing FFmpeg");
-                                    // If still running, force kill
-                                    _ = child.kill();
-                                    _ = child.wait();
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to stop FFmpeg: {:?}", e);
-                            warn!("Force killing FFmpeg");
-                            // If still running, force kill
-                            _ = child.kill();
-                            _ = child.wait();
-                        }
-                    }
-                }
-
-                // Wait for stdout and stderr threads to finish
-                stdout_handle.join().expect("Failed to join stdout thread");
-                stderr_handle.join().expect("Failed to join stderr thread");
+                let _ffmpeg_exited_tx = ffmpeg_exited_tx;
+                capture_video(
+                    &app_handle,
+                    &capture_args,
+                    &session_dir,
+                    &upload_queue,
+                    &ffmpeg_child,
+                    &is_recording,
+                    &is_paused,
+                );
             }
         });
 
@@ -347,19 +943,67 @@ impl RecorderState {
         // Start event capture in a separate thread
         let session = self.session.clone();
         let is_recording = self.is_recording.clone();
-        let main_window = self
-            .app_handle
-            .get_webview_window("main")
-            .expect("Failed to get main window");
+        let is_paused = self.is_paused.clone();
         let runtime = self.runtime.clone();
-        let runtime_clone = runtime.clone();
+        let app_handle = self.app_handle.clone();
         let event_handle = thread::spawn(move || {
-            event_capture_task(session, is_recording, main_window)
-                .expect("Failed to start event capture");
+            event_capture_task(
+                &app_handle,
+                session,
+                is_recording,
+                is_paused,
+                main_window,
+                display_layout,
+            );
         });
-        thread::spawn(move || {
-            monitor_segments(video_dir_path_clone, session_id, runtime_clone);
+
+        // Cleared once FFmpeg exited and its last segment was queued
+        let collecting_segments = Arc::new(AtomicBool::new(true));
+        let segments_handle = thread::spawn({
+            let upload_queue = upload_queue.clone();
+            let collecting_segments = collecting_segments.clone();
+            let session_dir = output_dir.clone();
+            move || {
+                monitor_segments(&session_dir, &upload_queue, &ffmpeg_exited);
+                collecting_segments.store(false, Ordering::SeqCst);
+            }
+        });
+        *self.segments_handle.lock().unwrap() = Some(segments_handle);
+
+        // Keeps going after the recording stopped until every chunk is uploaded or failed
+        let upload_handle = thread::spawn({
+            let session_dir = output_dir.clone();
+            let api = self.api.clone();
+            let runtime = runtime.clone();
+            let status = self.status.clone();
+            move || {
+                process_upload_queue(
+                    &upload_queue,
+                    &session_dir,
+                    session_id,
+                    &api,
+                    &runtime,
+                    &status,
+                    &collecting_segments,
+                );
+                if upload_queue.lock().unwrap().is_done() {
+                    Ok(())
+                } else {
+                    Err(RecorderError::Network(
+                        "Some chunks failed to upload, retrying on next start".to_string(),
+                    )
+                    .into())
+                }
+            }
         });
+        *self.upload_handle.lock().unwrap() = Some(upload_handle);
+
+        let is_recording = self.is_recording.clone();
+        let api = self.api.clone();
+        let journal_handle = thread::spawn(move || {
+            stream_events(&output_dir, &session_info, &api, &is_recording, &runtime);
+        });
+        *self.journal_handle.lock().unwrap() = Some(journal_handle);
 
         *self
             .event_handle
@@ -367,70 +1011,391 @@ impl RecorderState {
             .expect("Failed to lock event_handle") = Some(event_handle);
 
         info!("Recording started successfully");
-        self.app_handle
-            .emit("recording_started", ())
-            .expect("Failed to emit event");
+        if let Err(e) = self.app_handle.emit("recording_started", ()) {
+            warn!("Failed to emit recording_started: {:?}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Mark a stretch without video in the session, from when FFmpeg was last seen working
+    fn start_video_gap(&self, since_nanos: i64) {
+        if let Some(s) = self.session.lock().unwrap().as_mut() {
+            s.start_video_gap(since_nanos);
+        }
+    }
+
+    fn end_video_gap(&self) {
+        if let Some(s) = self.session.lock().unwrap().as_mut() {
+            s.end_video_gap(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+        }
+    }
+
+    /// Stop capturing video and events without ending the session
+    fn pause_recording(&self) -> Result<(), RecorderError> {
+        let mut session_guard = self.session.lock().unwrap();
+        let Some(s) = session_guard.as_mut() else {
+            return Err(RecorderError::InvalidState(
+                "No active recording session".to_string(),
+            ));
+        };
+        if self.status.transition(RecorderPhase::Paused).is_err() {
+            return Err(RecorderError::InvalidState(
+                "Only a running recording can be paused".to_string(),
+            ));
+        }
+        // Set under the session lock, so no event lands after the marker
+        self.is_paused.store(true, Ordering::SeqCst);
+        s.pause(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+
+        info!("Recording paused");
+        if let Err(e) = self.app_handle.emit("recording_paused", s.id) {
+            warn!("Failed to emit recording_paused: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Pick up a paused session, FFmpeg starts a new run that continues the chunk numbering
+    fn resume_recording(&self) -> Result<(), RecorderError> {
+        let mut session_guard = self.session.lock().unwrap();
+        let Some(s) = session_guard.as_mut() else {
+            return Err(RecorderError::InvalidState(
+                "No active recording session".to_string(),
+            ));
+        };
+        if self.status.phase() != RecorderPhase::Paused {
+            return Err(RecorderError::InvalidState(
+                "Recording is not paused".to_string(),
+            ));
+        }
+        s.resume(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+        self.is_paused.store(false, Ordering::SeqCst);
+        self.status.transition(RecorderPhase::Recording)?;
 
+        info!("Recording resumed");
+        if let Err(e) = self.app_handle.emit("recording_resumed", s.id) {
+            warn!("Failed to emit recording_resumed: {:?}", e);
+        }
         Ok(())
     }
 
-    async fn stop_recording(&self) -> Result<()> {
+    /// End a recording that can't go on, keeping what was recorded so far, and report why. The
+    /// recorder is left in the error phase, from which a new recording can start.
+    fn fail_recording(&self, error: RecorderError) {
+        error!("Recording failed: {}", error);
+        self.emit_error(&error);
+        // The recording threads run before `start_recording` is done
+        while self.status.phase() == RecorderPhase::Starting {
+            thread::sleep(Duration::from_millis(50));
+        }
+        match self.status.phase() {
+            RecorderPhase::Recording | RecorderPhase::Paused => {
+                if let Err(e) = self.stop_recording() {
+                    warn!("Failed to stop the failed recording: {}", e);
+                }
+            }
+            // Already being stopped, the uploads report on their own
+            RecorderPhase::Stopping => return,
+            _ => {}
+        }
+        self.status.fail(error);
+    }
+
+    fn stop_recording(&self) -> Result<(), RecorderError> {
+        if self.session.lock().unwrap().is_none()
+            || self.status.transition(RecorderPhase::Stopping).is_err()
+        {
+            return Err(RecorderError::InvalidState(
+                "No active recording session".to_string(),
+            ));
+        }
+        // Once stopping, a failure can't leave the recorder stuck in `Stopping`. A session that
+        // wasn't stopped cleanly is repaired by `recover_sessions` on the next start.
+        let result = self.finish_recording();
+        if let Err(e) = &result {
+            *self.session.lock().unwrap() = None;
+            self.status.fail(e.clone());
+        }
+        result
+    }
+
+    /// Stop the recording threads, close the session and start uploading what is left of it
+    fn finish_recording(&self) -> Result<(), RecorderError> {
         // Signal threads to stop
         self.is_recording.store(false, Ordering::SeqCst);
 
         // Wait for FFmpeg thread to finish
-        if let Some(handle) = self.ffmpeg_handle.lock().unwrap().take() {
-            handle.join().unwrap();
-        }
+        join_thread(&self.ffmpeg_handle, "FFmpeg")?;
+
+        // FFmpeg closed the last segment on exit, wait for it to be queued for upload
+        join_thread(&self.segments_handle, "segment")?;
 
         // Wait for event capture thread to finish
-        if let Some(handle) = self.event_handle.lock().unwrap().take() {
-            handle.join().unwrap();
-        }
+        join_thread(&self.event_handle, "event capture")?;
+
+        // Wait for the journal streaming thread, so it doesn't race the final upload
+        join_thread(&self.journal_handle, "journal streaming")?;
 
         info!("Stopping recording");
 
+        let Some(mut s) = self.session.lock().unwrap().take() else {
+            return Err(RecorderError::InvalidState(
+                "No active recording session".to_string(),
+            ));
+        };
+        self.is_paused.store(false, Ordering::SeqCst);
+
         // Save events to echo
-        let mut session_guard = self.session.lock().unwrap();
-        if let Some(s) = session_guard.as_mut() {
-            let runtime = self.runtime.clone();
-            let events = std::mem::take(&mut s.events); // Take ownership of events, leaving an empty Vec in its place
-            let wrapper = DeventRequestWrapper { events };
-
-            runtime.spawn(async move {
-                let client = reqwest::Client::new();
-                let res = client
-                    .post(format!("{BASE_URL}/devents/create"))
-                    .json(&wrapper)
-                    .send()
-                    .await;
-
-                match res {
-                    Ok(_) => info!("Event saved successfully"),
-                    Err(e) => error!("Failed to send request: {:?}", e),
-                }
-            });
-            self.app_handle
-                .emit("recording_complete", "sent to echo")
-                .unwrap();
-        } else {
-            return Err(anyhow!("No active recording session"));
+        s.flush_trajectory();
+        s.release_all_keys(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+        s.write_pending();
+        // Without the marker the session is repaired on the next start
+        let synced = s.journal.sync();
+        if let Err(e) = synced.and_then(|()| recovery::mark(&s.output_dir, STOPPED_FILE)) {
+            error!("Failed to sync event journal: {:?}", e);
         }
 
-        // Clear the session after stopping
-        *session_guard = None;
+        // Send whatever the streaming thread didn't get to, then wait for the chunks. If that
+        // fails the session is picked up by `recover_sessions` on the next start.
+        let api = self.api.clone();
+        let runtime = self.runtime.clone();
+        let output_dir = s.output_dir.clone();
+        let info = s.info.clone();
+        self.status.transition(RecorderPhase::Uploading)?;
+        let upload_handle = self.upload_handle.lock().unwrap().take();
+        let status = self.status.clone();
+        thread::spawn(move || {
+            let result = upload_journal(&output_dir, &info, &api, &runtime)
+                .and_then(|()| recovery::mark(&output_dir, EVENTS_UPLOADED_FILE));
+            match &result {
+                Ok(()) => info!("Events saved successfully"),
+                Err(e) => error!("Failed to upload events: {:?}", e),
+            }
+            let result = result.and(match upload_handle.map(JoinHandle::join) {
+                Some(Ok(result)) => result,
+                Some(Err(_)) => Err(anyhow!("Upload thread panicked")),
+                None => Ok(()),
+            });
+            status.finish_uploads(info.session_id, result);
+        });
+        if let Err(e) = self.app_handle.emit("recording_complete", "sent to echo") {
+            warn!("Failed to emit recording_complete: {:?}", e);
+        }
 
         Ok(())
     }
 }
 
+/// Wait for one of the recording threads to end, reporting it if it panicked
+fn join_thread(handle: &Mutex<Option<JoinHandle<()>>>, name: &str) -> Result<(), RecorderError> {
+    match handle.lock().unwrap().take().map(JoinHandle::join) {
+        Some(Err(_)) => Err(RecorderError::Other(format!(
+            "The {} thread panicked",
+            name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Hand a failure in one of the recording threads to the recorder. Stopping joins those
+/// threads, so it happens on a thread of its own.
+fn report_failure(app_handle: &AppHandle, error: RecorderError) {
+    let app_handle = app_handle.clone();
+    thread::spawn(move || app_handle.state::<RecorderState>().fail_recording(error));
+}
+
+/// Run FFmpeg until the recording stops. Pausing stops FFmpeg, so the open segment is closed
+/// and listed, and resuming starts a new run with its own segment list. FFmpeg that exits on its
+/// own or stops reporting progress is restarted into a new run the same way, leaving a gap in
+/// the video. FFmpeg failing to start, or failing again and again, fails the recording.
+fn capture_video(
+    app_handle: &AppHandle,
+    capture_args: &CaptureArgs,
+    session_dir: &Path,
+    upload_queue: &Mutex<UploadQueue>,
+    ffmpeg_child: &Mutex<Option<FfmpegChild>>,
+    is_recording: &AtomicBool,
+    is_paused: &AtomicBool,
+) {
+    let recordings_dir = session_dir.join("recordings");
+    let mut run = 0;
+    let mut restart_policy = RestartPolicy::default();
+    while is_recording.load(Ordering::SeqCst) {
+        if is_paused.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+
+        let first_chunk = segments::next_chunk_index(&recordings_dir);
+        if let Err(e) = segments::record_run(session_dir, run, first_chunk) {
+            warn!("Failed to record run {}: {:?}", run, e);
+        }
+        let run_args = capture_args.clone().first_chunk(first_chunk).outputs(
+            recordings_dir
+                .join(capture_args.chunk_pattern())
+                .to_str()
+                .unwrap(),
+            session_dir.join(segment_list_file(run)).to_str().unwrap(),
+            session_dir.join(timestamps_file(run)).to_str().unwrap(),
+        );
+        let child_result = get_ffmpeg_command(&run_args).spawn();
+
+        let mut child = match child_result {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to start FFmpeg: {}", e);
+                error!("Error kind: {:?}", e.kind());
+                error!("Raw OS error: {:?}", e.raw_os_error());
+
+                // If it's a "not found" error, it might be a PATH issue
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    error!("FFmpeg command not found. Check if FFmpeg is installed and in PATH.");
+                    if let Ok(path) = std::env::var("PATH") {
+                        error!("Current PATH: {}", path);
+                    }
+                    report_failure(app_handle, RecorderError::FfmpegMissing(e.to_string()));
+                } else {
+                    report_failure(app_handle, RecorderError::FfmpegExited(e.to_string()));
+                }
+                return;
+            }
+        };
+
+        let stdout = child.take_stdout().expect("Failed to get stdout");
+        let stderr = child.take_stderr().expect("Failed to get stderr");
+        let watchdog = Arc::new(FfmpegWatchdog::new());
+
+        *ffmpeg_child.lock().expect("Failed to lock ffmpeg_child") = Some(child);
+        app_handle.state::<RecorderState>().end_video_gap();
+
+        let stdout_handle = thread::spawn({
+            let watchdog = watchdog.clone();
+            move || {
+                let reader = BufReader::new(stdout);
+                for line in reader.lines() {
+                    if let Ok(line) = line {
+                        watchdog.observe_progress(&line);
+                    }
+                }
+            }
+        });
+
+        let stderr_handle = thread::spawn({
+            let watchdog = watchdog.clone();
+            move || {
+                let reader = BufReader::new(stderr);
+                for line in reader.lines() {
+                    if let Ok(line) = line {
+                        debug!("[ffmpeg stderr] {}", line);
+                        watchdog.observe_log(line);
+                    }
+                }
+            }
+        });
+
+        info!("Capture run {} started at chunk {}", run, first_chunk);
+        let mut failure = None;
+        while is_recording.load(Ordering::SeqCst) && !is_paused.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+            if let Some(child) = ffmpeg_child.lock().unwrap().as_mut() {
+                if let Ok(Some(status)) = child.as_inner_mut().try_wait() {
+                    failure = Some(RunFailure::Exited(status));
+                    break;
+                }
+            }
+            if watchdog.stalled() {
+                failure = Some(RunFailure::Stalled);
+                break;
+            }
+        }
+
+        if let Some(mut child) = ffmpeg_child
+            .lock()
+            .expect("Failed to lock ffmpeg_child")
+            .take()
+        {
+            if failure.is_some() {
+                // Already gone, or hung and wouldn't read the quit command
+                _ = child.kill();
+                _ = child.wait();
+            } else {
+                // Gracefully stop FFmpeg
+                info!("Stopping FFmpeg");
+                match child.quit() {
+                    Ok(_) => {
+                        match child.wait() {
+                            Ok(exit_status) => info!("FFmpeg stopped with {:?}", exit_status),
+                            Err(e) => {
+                                error!("Failed to stop FFmpeg: {:?}", e);
+                                warn!("Force killing FFmpeg");
+                                // If still running, force kill
+                                _ = child.kill();
+                                _ = child.wait();
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to stop FFmpeg: {:?}", e);
+                        warn!("Force killing FFmpeg");
+                        // If still running, force kill
+                        _ = child.kill();
+                        _ = child.wait();
+                    }
+                }
+            }
+        }
+
+        // Wait for stdout and stderr threads to finish
+        stdout_handle.join().expect("Failed to join stdout thread");
+        stderr_handle.join().expect("Failed to join stderr thread");
+
+        if let Some(failure) = failure {
+            let error = watchdog.error(&failure);
+            error!(
+                "FFmpeg run {} {} after {} frames: {}",
+                run,
+                failure,
+                watchdog.frames(),
+                error
+            );
+            // FFmpeg didn't get to close and list the chunk it was writing
+            match recovery::repair_unlisted_chunks(session_dir, run) {
+                Ok(chunks) => {
+                    for chunk in chunks {
+                        if let Err(e) = upload_queue.lock().unwrap().enqueue(&chunk) {
+                            error!("Failed to queue {}: {:?}", chunk, e);
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to repair the chunks of run {}: {:?}", run, e),
+            }
+            if !restart_policy.allow_restart(watchdog.started(), Instant::now(), &error) {
+                report_failure(app_handle, error);
+                return;
+            }
+            warn!("Restarting FFmpeg");
+            app_handle
+                .state::<RecorderState>()
+                .start_video_gap(watchdog.last_alive_nanos());
+            thread::sleep(RESTART_DELAY);
+        }
+        run += 1;
+    }
+}
+
+/// Record input events until the recording stops. Failing to hook into the input events fails
+/// the recording.
 fn event_capture_task(
+    app_handle: &AppHandle,
     session: Arc<Mutex<Option<RecordingSession>>>,
     is_recording: Arc<AtomicBool>,
+    is_paused: Arc<AtomicBool>,
     main_window: WebviewWindow,
-) -> Result<()> {
+    display_layout: DisplayLayout,
+) {
     let mut last_mouse_pos = (0.0, 0.0);
-    let _ = listen(move |event| {
+    let mut window_watcher = WindowWatcher::new(default_provider());
+    let result = listen(move |event| {
         if !is_recording.load(Ordering::SeqCst) {
             return;
         }
@@ -441,163 +1406,314 @@ fn event_capture_task(
             None => return, // After the year 2262 this always be the case
         };
 
-        // Get the scale factor
-        let scale_factor = main_window.scale_factor().unwrap_or(1.0);
-
         // Update last known mouse position if this is a mouse move event
         if let EventType::MouseMove { x, y } = event.event_type {
-            last_mouse_pos = (x * scale_factor, y * scale_factor);
-            // Do not record MouseMove events
-            return;
+            last_mouse_pos = display_layout.to_global_pixels(x, y);
+        }
+
+        // NOTE: drag halts mousemove on some platforms, so ask the window system where the cursor
+        // is whenever a button changes state instead of trusting the last move event
+        if let EventType::ButtonPress(_) | EventType::ButtonRelease(_) = event.event_type {
+            if let Ok(position) = main_window.cursor_position() {
+                last_mouse_pos = (position.x, position.y);
+            }
         }
 
-        // NOTE: drag halts mousemove so we need to update last_mouse_pos here, not a nice way to
-        // do that with rdev so we can use window or something
+        let mouse_pos = (last_mouse_pos.0 as i32, last_mouse_pos.1 as i32);
+        let timestamp = timestamp as i64;
+        let window = match event.event_type {
+            EventType::MouseMove { .. } | EventType::Wheel { .. } => window_watcher.current(),
+            _ => window_watcher.refresh(),
+        };
 
         let mut session_guard = session.lock().unwrap();
+        // Checked under the lock, pausing sets it while holding the session
+        if is_paused.load(Ordering::SeqCst) {
+            return;
+        }
         if let Some(s) = session_guard.as_mut() {
-            let mut devent_request = DeventRequest {
-                session_id: s.id,
-                mouse_action: None,
-                keyboard_action: None,
-                scroll_action: None,
-                mouse_x: last_mouse_pos.0 as i32,
-                mouse_y: last_mouse_pos.1 as i32,
-                event_timestamp_nanos: timestamp as i64,
-            };
+            s.mouse_pos = mouse_pos;
+            s.set_window(window, timestamp, mouse_pos);
+
+            if !matches!(event.event_type, EventType::MouseMove { .. }) {
+                s.flush_trajectory();
+            }
 
             match event.event_type {
+                EventType::MouseMove { .. } => {
+                    s.move_pointer(timestamp, mouse_pos);
+                }
                 EventType::ButtonPress(btn) => {
-                    let mouse_action: MouseAction = btn.into();
-                    devent_request.mouse_action = Some(mouse_action);
+                    s.press_button(btn, timestamp, mouse_pos);
+                }
+                EventType::ButtonRelease(btn) => {
+                    s.release_button(btn, timestamp, mouse_pos);
                 }
                 EventType::KeyPress(key) => {
-                    let keyboard_action: KeyboardActionKey = key.into();
-                    devent_request.keyboard_action = Some(KeyboardAction {
-                        key: keyboard_action,
-                        duration: 100, // TODO: make this dynamic by tracking keypress and keyrelease events
-                    });
+                    // Keyboard actions are recorded on release, once the hold duration is known
+                    let text = event.name.filter(|name| !name.is_empty());
+                    s.press_key(key, text, timestamp, mouse_pos);
+                }
+                EventType::KeyRelease(key) => {
+                    s.release_key(key, timestamp);
                 }
                 EventType::Wheel { delta_x, delta_y } => {
-                    let scroll_action: ScrollAction = ScrollAction {
+                    let mut devent_request =
+                        DeventRequest::new(s.id, s.context(), mouse_pos, timestamp);
+                    devent_request.scroll_action = Some(ScrollAction {
                         x: delta_x as i32,
                         y: delta_y as i32,
-                    };
-                    devent_request.scroll_action = Some(scroll_action);
+                    });
+                    s.push_event(devent_request);
                 }
-                _ => return,
             };
-
-            s.events.push(devent_request);
         }
-    })
-    .map_err(|e| anyhow!("Event capture failed: {:?}", e));
-    Ok(())
+    });
+    if let Err(e) = result {
+        error!("Event capture failed: {:?}", e);
+        report_failure(
+            app_handle,
+            RecorderError::InputHookFailed(format!("{:?}", e)),
+        );
+    }
 }
 
-fn monitor_segments(recording_dir_path: PathBuf, session_id: Uuid, runtime: Arc<TokioRuntime>) {
-    let mut saved_segs: u32 = 0;
+/// Periodically send journaled events to the server until the recording stops. Failed uploads
+/// are retried on the next tick, the events stay on disk until they went through.
+fn stream_events(
+    output_dir: &Path,
+    info: &SessionInfo,
+    api: &ApiClient,
+    is_recording: &AtomicBool,
+    runtime: &TokioRuntime,
+) {
+    let mut uploaded_at = Instant::now();
+    while is_recording.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(100));
+        if uploaded_at.elapsed() < EVENT_UPLOAD_INTERVAL {
+            continue;
+        }
+        uploaded_at = Instant::now();
 
+        if let Err(e) = upload_journal(output_dir, info, api, runtime) {
+            warn!("Failed to upload events, retrying later: {:?}", e);
+        }
+    }
+}
+
+/// Upload every journaled event after the cursor in batches, advancing the cursor after each.
+/// Events of a batch the server accepted just before a crash are sent again, with the same
+/// `sequence`, see `DeventRequestWrapper`.
+pub fn upload_journal(
+    output_dir: &Path,
+    info: &SessionInfo,
+    api: &ApiClient,
+    runtime: &TokioRuntime,
+) -> Result<()> {
     loop {
-        thread::sleep(Duration::from_secs(5));
-
-        let highest_file_num = fs::read_dir(&recording_dir_path)
-            .expect("Failed to read directory")
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                let file_name = entry.file_name().into_string().ok()?;
-                if file_name.ends_with(".mkv") {
-                    file_name[6..10].parse::<u32>().ok()
-                } else {
-                    None
-                }
-            })
-            .max()
-            .unwrap_or(0);
+        let batch = journal::read_batch::<DeventRequest>(output_dir, EVENT_BATCH_SIZE)?;
+        if batch.events.is_empty() {
+            return Ok(());
+        }
 
-        info!(
-            "highest_file_num: {}, saved_segs: {}",
-            highest_file_num, saved_segs
-        );
+        let count = batch.events.len();
+        let wrapper = DeventRequestWrapper {
+            session: info.clone(),
+            events: batch.events,
+        };
+        runtime.block_on(api.send(api.post("/devents/create").json(&wrapper)))?;
 
-        if highest_file_num >= saved_segs {
-            let file_to_upload = format!("chunk_{:04}.mkv", highest_file_num);
-            let file_path = recording_dir_path.join(&file_to_upload);
-
-            if file_path.exists() {
-                let client = reqwest::Client::new();
-                let recording_dir_path_clone = recording_dir_path.clone();
-                let session_id_clone = session_id;
-                info!("uploading...");
-                runtime.spawn(async move {
-                    upload_file(
-                        &client,
-                        &recording_dir_path_clone,
-                        &file_to_upload,
-                        session_id_clone,
-                    )
-                    .await;
-                });
+        journal::write_cursor(output_dir, batch.end_offset)?;
+        debug!("Uploaded {} events", count);
+    }
+}
 
-                saved_segs = highest_file_num + 1;
+/// Queue every segment FFmpeg finished for upload. FFmpeg appends a segment to the segment list
+/// once it closed the file, so listed chunks are complete. Returns after FFmpeg exited and the
+/// final segment was queued.
+fn monitor_segments(
+    session_dir: &Path,
+    upload_queue: &Mutex<UploadQueue>,
+    ffmpeg_exited: &Receiver<()>,
+) {
+    let mut queued = 0;
+    loop {
+        let exited = match ffmpeg_exited.recv_timeout(Duration::from_secs(1)) {
+            Err(RecvTimeoutError::Timeout) => false,
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        match segments::finished_segments(session_dir) {
+            Ok(segments) => {
+                for segment in segments.iter().skip(queued) {
+                    if let Err(e) = upload_queue.lock().unwrap().enqueue(&segment.file_name) {
+                        error!("Failed to queue {}: {:?}", segment.file_name, e);
+                        break;
+                    }
+                    debug!("Queued {} for upload", segment.file_name);
+                    queued += 1;
+                }
             }
+            Err(e) => warn!("Failed to read segment list: {:?}", e),
+        }
+
+        if exited {
+            info!("Queued {} segments", queued);
+            return;
         }
     }
 }
-// https://echo.i.inc/recordings/fetch_save_url
-async fn upload_file(
-    client: &reqwest::Client,
-    recording_dir_path: &Path,
-    file_name: &str,
+
+/// Upload the chunks of a session's queue one at a time, retrying failures with backoff. Runs
+/// until `keep_running` is cleared and no chunk is left pending.
+pub fn process_upload_queue(
+    upload_queue: &Mutex<UploadQueue>,
+    session_dir: &Path,
     session_id: Uuid,
+    api: &ApiClient,
+    runtime: &TokioRuntime,
+    status: &StatusTracker,
+    keep_running: &AtomicBool,
 ) {
-    let res = client
-        .post(format!("{BASE_URL}/recordings/fetch_save_url"))
-        .json(&SaveRecordingRequest {
-            recording_id: Uuid::new_v4(),
-            session_id,
-            start_timestamp_nanos: 0, // You might want to calculate this
-            duration_ms: 0,           // You might want to calculate this
-        })
-        .send()
-        .await;
-
-    match res {
-        Ok(res) => {
-            let url = res.text().await.unwrap();
-            let video_file_path = recording_dir_path.join(file_name);
-
-            match fs::read(&video_file_path) {
-                Ok(video_content) => {
-                    let upload_res = client
-                        .put(url)
-                        .header("Content-Type", "video/x-matroska")
-                        .body(video_content)
-                        .send()
-                        .await;
-
-                    match upload_res {
-                        Ok(_) => info!("Uploaded recording {} successfully", file_name),
-                        Err(e) => error!("Failed to upload recording {}: {:?}", file_name, e),
-                    }
+    loop {
+        let next = upload_queue.lock().unwrap().start_next();
+        let chunk = match next {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                if !keep_running.load(Ordering::SeqCst) && upload_queue.lock().unwrap().is_settled()
+                {
+                    return;
                 }
-                Err(e) => error!("Failed to read file {}: {:?}", file_name, e),
+                thread::sleep(Duration::from_millis(500));
+                continue;
+            }
+            Err(e) => {
+                error!("Upload queue failed: {:?}", e);
+                return;
+            }
+        };
+        emit_upload_progress(upload_queue, session_id, chunk.clone(), status);
+
+        let result = runtime.block_on(upload_file(api, session_dir, &chunk, session_id));
+        let mut queue = upload_queue.lock().unwrap();
+        let updated = match result {
+            Ok(()) => {
+                info!("Uploaded recording {} successfully", chunk.file_name);
+                queue.finish(&chunk.file_name)
             }
+            Err(e) => {
+                warn!("Failed to upload recording {}: {:?}", chunk.file_name, e);
+                queue.fail(&chunk.file_name, &e.to_string())
+            }
+        };
+        drop(queue);
+        match updated {
+            Ok(chunk) => emit_upload_progress(upload_queue, session_id, chunk, status),
+            Err(e) => error!("Failed to update upload queue: {:?}", e),
         }
-        Err(e) => error!("Failed to send request for {}: {:?}", file_name, e),
     }
 }
 
+fn emit_upload_progress(
+    upload_queue: &Mutex<UploadQueue>,
+    session_id: Uuid,
+    chunk: ChunkUpload,
+    status: &StatusTracker,
+) {
+    let progress = upload_queue.lock().unwrap().progress(session_id, chunk);
+    status.upload_progress(progress);
+}
+
+// https://echo.i.inc/recordings/fetch_save_url
+async fn upload_file(
+    api: &ApiClient,
+    session_dir: &Path,
+    chunk: &ChunkUpload,
+    session_id: Uuid,
+) -> Result<()> {
+    let file_name = &chunk.file_name;
+    let timing = segments::chunk_timing(session_dir, file_name)
+        .with_context(|| format!("Failed to compute timing of {}", file_name))?;
+    let request = api
+        .post("/recordings/fetch_save_url")
+        .json(&SaveRecordingRequest {
+            recording_id: chunk.recording_id,
+            session_id,
+            chunk_index: chunk.index,
+            start_timestamp_nanos: timing.start_timestamp_nanos,
+            duration_ms: timing.duration_ms,
+        });
+    let url = api
+        .send(request)
+        .await
+        .with_context(|| format!("Failed to fetch the save url for {}", file_name))?
+        .text()
+        .await?;
+
+    let video_content = fs::read(session_dir.join("recordings").join(file_name))
+        .with_context(|| format!("Failed to read file {}", file_name))?;
+    let container = Container::of_file(file_name).unwrap_or_default();
+    api.put_presigned(&url, container.mime_type(), video_content)
+        .await
+}
+
 #[tauri::command]
-pub fn start_recording(state: State<'_, RecorderState>) {
-    info!("Ffmpeg installed: {:?}", ffmpeg_is_installed());
-    auto_download().unwrap_or_else(|e| error!("Failed to download ffmpeg: {:?}", e));
+pub fn start_recording(state: State<'_, RecorderState>) -> Result<(), RecorderError> {
+    state.start_recording()
+}
 
-    _ = state.start_recording();
+/// Async so joining the recording threads doesn't hold up the main thread
+#[tauri::command]
+pub async fn stop_recording(state: State<'_, RecorderState>) -> Result<(), RecorderError> {
+    state.stop_recording()
+}
+
+#[tauri::command]
+pub fn get_recorder_status(state: State<'_, RecorderState>) -> RecorderStatus {
+    state.status()
+}
+
+#[tauri::command]
+pub fn pause_recording(state: State<'_, RecorderState>) -> Result<(), RecorderError> {
+    state.pause_recording()
+}
+
+#[tauri::command]
+pub fn resume_recording(state: State<'_, RecorderState>) -> Result<(), RecorderError> {
+    state.resume_recording()
+}
+
+#[tauri::command]
+pub fn set_fsync_policy(state: State<'_, RecorderState>, policy: FsyncPolicy) {
+    state.set_fsync_policy(policy);
+}
+
+#[tauri::command]
+pub fn list_displays(state: State<'_, RecorderState>) -> Result<Vec<DisplayInfo>, RecorderError> {
+    Ok(state.list_displays()?)
+}
+
+#[tauri::command]
+pub fn list_windows(state: State<'_, RecorderState>) -> Result<Vec<WindowContext>, RecorderError> {
+    state.list_windows()
+}
+
+#[tauri::command]
+pub fn set_capture_target(
+    state: State<'_, RecorderState>,
+    target: CaptureTarget,
+) -> Result<(), RecorderError> {
+    state.set_capture_target(target)
 }
 
 #[tauri::command]
-pub async fn stop_recording(state: State<'_, RecorderState>) -> Result<(), String> {
-    state.stop_recording().await.map_err(|e| e.to_string())?;
+pub fn set_trajectory_sampling(
+    state: State<'_, RecorderState>,
+    sampling: TrajectorySampling,
+) -> Result<(), RecorderError> {
+    sampling
+        .validate()
+        .map_err(|e| RecorderError::InvalidConfig(e.to_string()))?;
+    state.set_trajectory_sampling(sampling);
     Ok(())
 }
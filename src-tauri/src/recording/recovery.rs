@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{atomic::AtomicBool, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use ffmpeg_sidecar::{command::FfmpegCommand, event::FfmpegEvent};
+use log::{debug, info, warn};
+use tauri::async_runtime::TokioRuntime;
+
+use super::capture::Container;
+use super::recording::{process_upload_queue, upload_journal, SessionInfo};
+use super::segments::{
+    capture_runs, chunk_files, chunk_index, run_of_chunk, run_segments, segment_list_file,
+};
+use super::status::StatusTracker;
+use super::upload_queue::UploadQueue;
+use crate::api::ApiClient;
+
+/// Written once ffmpeg exited and the journal was synced, the session files are complete
+pub const STOPPED_FILE: &str = "stopped";
+/// Written once every event of the session was uploaded
+pub const EVENTS_UPLOADED_FILE: &str = "events_uploaded";
+
+pub fn mark(session_dir: &Path, marker: &str) -> Result<()> {
+    fs::write(session_dir.join(marker), "").with_context(|| format!("Failed to mark {}", marker))
+}
+
+/// Session directories under `output_root` with events or chunks left to upload, oldest first
+pub fn unfinished_sessions(output_root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(output_root) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|dir| dir.join("session.json").exists())
+        .filter(|dir| !dir.join(EVENTS_UPLOADED_FILE).exists() || UploadQueue::has_unfinished(dir))
+        .collect();
+    // Directory names are start timestamps
+    sessions.sort();
+    sessions
+}
+
+/// Bring an unfinished session to the state a clean stop would have left it in, then upload
+/// what the server hasn't acknowledged yet
+pub fn recover_session(
+    session_dir: &Path,
+    api: &ApiClient,
+    runtime: &TokioRuntime,
+    status: &StatusTracker,
+) -> Result<()> {
+    let info_file =
+        fs::File::open(session_dir.join("session.json")).context("Failed to open session info")?;
+    let info: SessionInfo =
+        serde_json::from_reader(info_file).context("Failed to read session info")?;
+    info!(
+        "Recovering session {} in {:?}",
+        info.session_id, session_dir
+    );
+
+    let recordings_dir = session_dir.join("recordings");
+    let mut upload_queue = UploadQueue::load(session_dir)?;
+    if !session_dir.join(STOPPED_FILE).exists() {
+        // The app died while recording, ffmpeg never closed the chunk it was writing. Listed
+        // chunks are complete, only the ones missing from their run's list need repairing.
+        for run in capture_runs(session_dir) {
+            for chunk in repair_unlisted_chunks(session_dir, run)? {
+                // A repaired chunk replaces whatever was uploaded of it before
+                if upload_queue.was_uploaded(&chunk) {
+                    upload_queue.requeue(&chunk)?;
+                } else {
+                    upload_queue.enqueue(&chunk)?;
+                }
+            }
+        }
+        mark(session_dir, STOPPED_FILE)?;
+    }
+    // Chunks the app never got to queue
+    for chunk in chunk_files(&recordings_dir) {
+        upload_queue.enqueue(&chunk)?;
+    }
+
+    if !session_dir.join(EVENTS_UPLOADED_FILE).exists() {
+        upload_journal(session_dir, &info, api, runtime)?;
+        mark(session_dir, EVENTS_UPLOADED_FILE)?;
+    }
+
+    let upload_queue = Mutex::new(upload_queue);
+    let keep_running = AtomicBool::new(false);
+    process_upload_queue(
+        &upload_queue,
+        session_dir,
+        info.session_id,
+        api,
+        runtime,
+        status,
+        &keep_running,
+    );
+    if !upload_queue.into_inner().unwrap().is_done() {
+        return Err(anyhow!("Some chunks failed to upload"));
+    }
+    info!("Recovered session {}", info.session_id);
+    Ok(())
+}
+
+/// Drop a trailing line that was cut off mid-write
+fn truncate_to_last_line(path: &Path) -> Result<()> {
+    let Ok(contents) = fs::read(path) else {
+        return Ok(());
+    };
+    let complete = contents
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |index| index + 1);
+    if complete < contents.len() {
+        warn!("Truncating partial line in {:?}", path);
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(())
+}
+
+/// Salvage the chunks a run left out of its segment list because FFmpeg crashed or was killed,
+/// so they can be uploaded. Returns their file names.
+pub fn repair_unlisted_chunks(session_dir: &Path, run: u32) -> Result<Vec<String>> {
+    truncate_to_last_line(&session_dir.join(segment_list_file(run)))?;
+    let listed: HashSet<String> = run_segments(session_dir, run)?
+        .into_iter()
+        .map(|segment| segment.file_name)
+        .collect();
+    let recordings_dir = session_dir.join("recordings");
+    let mut repaired = Vec::new();
+    for chunk in chunk_files(&recordings_dir) {
+        let in_run = chunk_index(&chunk).and_then(|index| run_of_chunk(session_dir, index));
+        if in_run == Some(run) && !listed.contains(&chunk) && repair_chunk(&recordings_dir, &chunk)?
+        {
+            repaired.push(chunk);
+        }
+    }
+    Ok(repaired)
+}
+
+/// Remux a chunk ffmpeg didn't finish. Chunks that can't be salvaged are deleted. Returns
+/// whether the chunk was kept.
+fn repair_chunk(recordings_dir: &Path, chunk: &str) -> Result<bool> {
+    let chunk_path = recordings_dir.join(chunk);
+    let repaired_path = recordings_dir.join(format!("{}.repaired", chunk));
+    // Failing to run ffmpeg at all is an error, the chunk is only dropped when ffmpeg can't
+    // read anything from it
+    let container = Container::of_file(chunk).unwrap_or_default();
+    if remux(&chunk_path, &repaired_path, container)? {
+        fs::rename(&repaired_path, &chunk_path).context("Failed to replace chunk")?;
+        info!("Repaired {}", chunk);
+        Ok(true)
+    } else {
+        warn!("Dropping unrecoverable chunk {}", chunk);
+        _ = fs::remove_file(&repaired_path);
+        fs::remove_file(&chunk_path).context("Failed to delete chunk")?;
+        Ok(false)
+    }
+}
+
+/// Copy the streams of `input` into a fresh container, keeping every frame ffmpeg can read.
+/// Returns whether anything could be salvaged.
+fn remux(input: &Path, output: &Path, container: Container) -> Result<bool> {
+    let mut child = FfmpegCommand::new()
+        .input(input.to_string_lossy())
+        .args(["-c", "copy"])
+        .args(["-f", container.muxer()])
+        .overwrite()
+        .output(output.to_string_lossy())
+        .spawn()?;
+    // Drain the output so ffmpeg doesn't block on a full pipe
+    child.iter()?.for_each(|event| {
+        if let FfmpegEvent::Log(_, line) = event {
+            debug!("[ffmpeg log] {}", line);
+        }
+    });
+
+    let status = child.wait()?;
+    let size = fs::metadata(output)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    debug!("Remux of {:?} exited with {:?}", input, status);
+    Ok(status.success() && size > 0)
+}
@@ -0,0 +1,375 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+use super::capture::Container;
+
+/// Segment list FFmpeg writes to the session directory
+pub const SEGMENT_LIST_FILE: &str = "segments.csv";
+/// Presentation timestamp of every frame, `mkvtimestamp_v2` format
+pub const TIMESTAMPS_FILE: &str = "timestamps.txt";
+
+/// First chunk of every run, `run,first_chunk` per line. Tells which run wrote a chunk that
+/// FFmpeg never got to list.
+pub const RUNS_FILE: &str = "runs.csv";
+
+/// Timestamps above this are wall clock milliseconds rather than offsets into the stream
+const EPOCH_THRESHOLD_MS: f64 = 1e12;
+
+/// A finished segment from FFmpeg's csv segment list
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// The FFmpeg process that wrote the segment, see `segment_list_file`
+    pub run: u32,
+    pub file_name: String,
+    pub start_secs: f64,
+    pub end_secs: f64,
+}
+
+/// Wall clock position of a chunk, for lining it up with the event stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkTiming {
+    pub start_timestamp_nanos: i64,
+    pub duration_ms: u64,
+}
+
+/// Segment list of the `run`th FFmpeg process of a session. Capture restarts after a pause, and
+/// each process writes its own list and timestamps since FFmpeg truncates them on start. Chunk
+/// numbers carry on across runs.
+pub fn segment_list_file(run: u32) -> String {
+    match run {
+        0 => SEGMENT_LIST_FILE.to_string(),
+        run => format!("segments_{}.csv", run),
+    }
+}
+
+/// Frame timestamps of the `run`th FFmpeg process of a session
+pub fn timestamps_file(run: u32) -> String {
+    match run {
+        0 => TIMESTAMPS_FILE.to_string(),
+        run => format!("timestamps_{}.txt", run),
+    }
+}
+
+/// The FFmpeg runs of a session that left a segment list or timestamps behind, in order
+pub fn capture_runs(session_dir: &Path) -> Vec<u32> {
+    let Ok(entries) = fs::read_dir(session_dir) else {
+        return Vec::new();
+    };
+    let run_of = |name: &str| {
+        if name == SEGMENT_LIST_FILE || name == TIMESTAMPS_FILE {
+            return Some(0);
+        }
+        name.strip_prefix("segments_")
+            .and_then(|rest| rest.strip_suffix(".csv"))
+            .or_else(|| {
+                name.strip_prefix("timestamps_")
+                    .and_then(|rest| rest.strip_suffix(".txt"))
+            })?
+            .parse()
+            .ok()
+    };
+    let mut runs: Vec<u32> = entries
+        .filter_map(|entry| run_of(entry.ok()?.file_name().to_str()?))
+        .collect();
+    runs.sort();
+    runs.dedup();
+    runs
+}
+
+/// Note the first chunk of a run before FFmpeg starts writing it
+pub fn record_run(session_dir: &Path, run: u32, first_chunk: u32) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(session_dir.join(RUNS_FILE))
+        .context("Failed to open run list")?;
+    writeln!(file, "{},{}", run, first_chunk).context("Failed to write run list")
+}
+
+/// The run that wrote a chunk: the last one started at or before its index. `None` for sessions
+/// recorded before runs were listed.
+pub fn run_of_chunk(session_dir: &Path, index: u32) -> Option<u32> {
+    let contents = fs::read_to_string(session_dir.join(RUNS_FILE)).ok()?;
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (run, first_chunk) = line.trim().split_once(',')?;
+            Some((run.parse::<u32>().ok()?, first_chunk.parse::<u32>().ok()?))
+        })
+        .filter(|(_, first_chunk)| *first_chunk <= index)
+        .max_by_key(|(run, first_chunk)| (*first_chunk, *run))
+        .map(|(run, _)| run)
+}
+
+/// Index of a chunk from its `chunk_%04d.mkv` (or other container) file name
+pub fn chunk_index(file_name: &str) -> Option<u32> {
+    let extension = Container::of_file(file_name)?.extension();
+    file_name
+        .strip_prefix("chunk_")?
+        .strip_suffix(extension)?
+        .strip_suffix('.')?
+        .parse()
+        .ok()
+}
+
+/// Number of the chunk after the highest numbered one in `recordings_dir`
+pub fn next_chunk_index(recordings_dir: &Path) -> u32 {
+    let Ok(entries) = fs::read_dir(recordings_dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| chunk_index(entry.ok()?.file_name().to_str()?))
+        .max()
+        .map_or(0, |index| index + 1)
+}
+
+/// File names of the video chunks of a session, in recording order
+pub fn chunk_files(recordings_dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(recordings_dir) else {
+        return Vec::new();
+    };
+    let mut chunks: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("chunk_") && Container::of_file(name).is_some())
+        .collect();
+    chunks.sort();
+    chunks
+}
+
+/// The segments of every run of a session, in order
+pub fn finished_segments(session_dir: &Path) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for run in capture_runs(session_dir) {
+        segments.extend(run_segments(session_dir, run)?);
+    }
+    Ok(segments)
+}
+
+/// The segments in a run's csv segment list (`name,start,end` per line), in order. A line that
+/// is still being written is ignored.
+pub fn run_segments(session_dir: &Path, run: u32) -> Result<Vec<Segment>> {
+    let contents = match fs::read_to_string(session_dir.join(segment_list_file(run))) {
+        Ok(contents) => contents,
+        // Created when the first segment is done
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Failed to read segment list"),
+    };
+    let complete = contents.rfind('\n').map_or("", |end| &contents[..=end]);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(complete.as_bytes());
+    let mut segments = Vec::new();
+    for record in reader.records() {
+        let record = record.context("Invalid segment list")?;
+        let segment = match (record.get(0), record.get(1), record.get(2)) {
+            (Some(name), Some(start), Some(end)) if Container::of_file(name).is_some() => start
+                .parse()
+                .ok()
+                .zip(end.parse().ok())
+                .map(|(start_secs, end_secs)| Segment {
+                    run,
+                    file_name: name.to_string(),
+                    start_secs,
+                    end_secs,
+                }),
+            _ => None,
+        };
+        match segment {
+            Some(segment) => segments.push(segment),
+            None => warn!("Skipping unexpected segment list entry {:?}", record),
+        }
+    }
+    Ok(segments)
+}
+
+/// Frame timestamps of a run in milliseconds. The capture filter sets each frame's pts to the
+/// wall clock (`setpts='RTCTIME/1000'` in a 1/1000 time base), so these are unix millis.
+pub fn frame_timestamps(session_dir: &Path, run: u32) -> Result<Vec<f64>> {
+    let contents = fs::read_to_string(session_dir.join(timestamps_file(run)))
+        .context("Failed to read frame timestamps")?;
+    let timestamps = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.parse().ok())
+        .collect();
+    Ok(timestamps)
+}
+
+/// Compute when a chunk starts and how long it is. The segment list gives each segment's bounds
+/// in stream time, the first frame inside those bounds gives the wall clock start. A chunk that
+/// isn't listed (the last one of a crashed session or of a killed run) runs from the end of its
+/// run's last listed segment to the run's last frame.
+pub fn chunk_timing(session_dir: &Path, file_name: &str) -> Result<ChunkTiming> {
+    let segments = finished_segments(session_dir)?;
+    let run = match segments.iter().find(|s| s.file_name == file_name) {
+        Some(segment) => segment.run,
+        None => chunk_index(file_name)
+            .and_then(|index| run_of_chunk(session_dir, index))
+            // Sessions without a run list only ever lost the very last chunk
+            .or_else(|| capture_runs(session_dir).last().copied())
+            .unwrap_or(0),
+    };
+    let segments = run_segments(session_dir, run)?;
+    let timestamps = frame_timestamps(session_dir, run)?;
+    let first_frame = *timestamps
+        .first()
+        .ok_or_else(|| anyhow!("No frame timestamps"))?;
+    let last_frame = *timestamps.last().unwrap();
+
+    let (start_secs, end_secs) = match segments.iter().find(|s| s.file_name == file_name) {
+        Some(segment) => (segment.start_secs, Some(segment.end_secs)),
+        None => (segments.last().map_or(0.0, |last| last.end_secs), None),
+    };
+    // Segment bounds are in the stream's time base, which depending on the muxer is either the
+    // wall clock or an offset from the first frame
+    let to_millis = |secs: f64| {
+        let millis = secs * 1000.0;
+        if millis >= EPOCH_THRESHOLD_MS {
+            millis
+        } else {
+            first_frame + millis
+        }
+    };
+    let start_ms = to_millis(start_secs);
+    let end_ms = end_secs.map_or(last_frame, to_millis);
+
+    let start_ms = timestamps
+        .iter()
+        .copied()
+        .find(|timestamp| *timestamp >= start_ms && *timestamp < end_ms)
+        .unwrap_or(start_ms);
+    Ok(ChunkTiming {
+        // Through micros, nanos since the epoch don't fit an f64's mantissa
+        start_timestamp_nanos: (start_ms * 1000.0).round() as i64 * 1000,
+        duration_ms: (end_ms - start_ms).max(0.0) as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wall clock of the first frame, unix millis
+    const FIRST_FRAME_MS: f64 = 1_700_000_000_000.0;
+
+    /// A run with a frame every 100ms for 5 seconds
+    fn session_of(segment_list: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(SEGMENT_LIST_FILE), segment_list).unwrap();
+        let mut timestamps = "# timestamp format v2\n".to_string();
+        for frame in 0..50 {
+            timestamps += &format!("{}\n", FIRST_FRAME_MS + frame as f64 * 100.0);
+        }
+        fs::write(dir.path().join(TIMESTAMPS_FILE), timestamps).unwrap();
+        dir
+    }
+
+    fn timing(start_ms: f64, duration_ms: u64) -> ChunkTiming {
+        ChunkTiming {
+            start_timestamp_nanos: start_ms as i64 * 1_000_000,
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn relative_segment_bounds() {
+        let dir =
+            session_of("chunk_0000.mkv,0.000000,2.000000\nchunk_0001.mkv,2.000000,4.000000\n");
+        assert_eq!(
+            chunk_timing(dir.path(), "chunk_0000.mkv").unwrap(),
+            timing(FIRST_FRAME_MS, 2000)
+        );
+        assert_eq!(
+            chunk_timing(dir.path(), "chunk_0001.mkv").unwrap(),
+            timing(FIRST_FRAME_MS + 2000.0, 2000)
+        );
+    }
+
+    #[test]
+    fn epoch_segment_bounds() {
+        let dir = session_of(
+            "chunk_0000.mkv,1700000000.000000,1700000002.000000\n\
+             chunk_0001.mkv,1700000002.000000,1700000004.000000\n",
+        );
+        assert_eq!(
+            chunk_timing(dir.path(), "chunk_0000.mkv").unwrap(),
+            timing(FIRST_FRAME_MS, 2000)
+        );
+        assert_eq!(
+            chunk_timing(dir.path(), "chunk_0001.mkv").unwrap(),
+            timing(FIRST_FRAME_MS + 2000.0, 2000)
+        );
+    }
+
+    #[test]
+    fn start_snaps_to_the_first_frame_of_the_chunk() {
+        let dir =
+            session_of("chunk_0000.mkv,0.000000,2.050000\nchunk_0001.mkv,2.050000,4.000000\n");
+        assert_eq!(
+            chunk_timing(dir.path(), "chunk_0001.mkv").unwrap(),
+            timing(FIRST_FRAME_MS + 2100.0, 1900)
+        );
+    }
+
+    #[test]
+    fn truncated_last_line() {
+        let dir = session_of("chunk_0000.mkv,0.000000,2.000000\nchunk_0001.mkv,2.00");
+        let segments = run_segments(dir.path(), 0).unwrap();
+        assert_eq!(
+            segments,
+            [Segment {
+                run: 0,
+                file_name: "chunk_0000.mkv".to_string(),
+                start_secs: 0.0,
+                end_secs: 2.0,
+            }]
+        );
+        // The unlisted chunk runs from the last listed one to the last frame
+        assert_eq!(
+            chunk_timing(dir.path(), "chunk_0001.mkv").unwrap(),
+            timing(FIRST_FRAME_MS + 2000.0, 2900)
+        );
+    }
+
+    #[test]
+    fn unexpected_entries_are_skipped() {
+        let dir = session_of("chunk_0000.mkv,0.0,2.0\nnotes.txt,2.0,4.0\nchunk_0001.mkv,x,4.0\n");
+        let segments = run_segments(dir.path(), 0).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].file_name, "chunk_0000.mkv");
+    }
+
+    #[test]
+    fn run_of_chunk_at_the_boundary() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(run_of_chunk(dir.path(), 0), None);
+
+        record_run(dir.path(), 0, 0).unwrap();
+        record_run(dir.path(), 1, 3).unwrap();
+        assert_eq!(run_of_chunk(dir.path(), 0), Some(0));
+        assert_eq!(run_of_chunk(dir.path(), 2), Some(0));
+        // The first chunk of a run belongs to it, not to the run before
+        assert_eq!(run_of_chunk(dir.path(), 3), Some(1));
+        assert_eq!(run_of_chunk(dir.path(), 4), Some(1));
+
+        // A run that failed before writing a chunk shares its first chunk with the next one
+        record_run(dir.path(), 2, 5).unwrap();
+        record_run(dir.path(), 3, 5).unwrap();
+        assert_eq!(run_of_chunk(dir.path(), 4), Some(1));
+        assert_eq!(run_of_chunk(dir.path(), 5), Some(3));
+    }
+
+    #[test]
+    fn missing_segment_list_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(run_segments(dir.path(), 0).unwrap().is_empty());
+        assert!(chunk_timing(dir.path(), "chunk_0000.mkv").is_err());
+    }
+}
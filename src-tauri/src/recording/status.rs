@@ -0,0 +1,339 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Runtime, Wry};
+use uuid::Uuid;
+
+use super::error::RecorderError;
+use super::upload_queue::UploadProgress;
+
+/// Where the recorder is in the life of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum RecorderPhase {
+    Idle,
+    Starting,
+    Recording,
+    Paused,
+    Stopping,
+    /// Stopped, the last chunks and events are still being uploaded. A new recording can start.
+    Uploading,
+    Error,
+}
+
+impl RecorderPhase {
+    /// Whether the recorder may go from this phase to `next`
+    pub fn can_become(self, next: RecorderPhase) -> bool {
+        use RecorderPhase::*;
+        matches!(
+            (self, next),
+            (_, Error)
+                | (Idle | Uploading | Error, Starting)
+                | (Starting | Paused, Recording)
+                | (Recording, Paused)
+                | (Recording | Paused, Stopping)
+                | (Stopping, Uploading)
+                | (Uploading | Error, Idle)
+        )
+    }
+}
+
+/// Payload of the `recorder_state_changed` event and result of `get_recorder_status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecorderStatus {
+    pub state: RecorderPhase,
+    /// The session being recorded, or the last one
+    pub session_id: Option<Uuid>,
+    /// Time spent recording, pauses excluded
+    pub elapsed_ms: u64,
+    pub event_count: u64,
+    pub chunk_count: usize,
+    /// Chunks of the session that aren't uploaded yet
+    pub pending_uploads: usize,
+    /// What went wrong, in the `error` phase
+    pub error: Option<RecorderError>,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    phase: RecorderPhase,
+    session_id: Option<Uuid>,
+    /// Recording time up to the last pause
+    elapsed: Duration,
+    /// Start of the current stretch of recording
+    recording_since: Option<Instant>,
+    chunk_count: usize,
+    pending_uploads: usize,
+    error: Option<RecorderError>,
+}
+
+/// The recorder's phase and the counters reported along with it. Every phase change is
+/// announced with `recorder_state_changed`.
+pub struct StatusTracker<R: Runtime = Wry> {
+    app_handle: Arc<AppHandle<R>>,
+    tracked: Mutex<Tracked>,
+    /// Shared with the session, which counts journaled events
+    event_count: Arc<AtomicU64>,
+}
+
+impl<R: Runtime> StatusTracker<R> {
+    pub fn new(app_handle: Arc<AppHandle<R>>) -> Self {
+        StatusTracker {
+            app_handle,
+            tracked: Mutex::new(Tracked {
+                phase: RecorderPhase::Idle,
+                session_id: None,
+                elapsed: Duration::ZERO,
+                recording_since: None,
+                chunk_count: 0,
+                pending_uploads: 0,
+                error: None,
+            }),
+            event_count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn phase(&self) -> RecorderPhase {
+        self.tracked.lock().unwrap().phase
+    }
+
+    /// Move to `next` if that is allowed from the current phase
+    pub fn transition(&self, next: RecorderPhase) -> Result<()> {
+        let mut tracked = self.tracked.lock().unwrap();
+        let current = tracked.phase;
+        if !current.can_become(next) {
+            return Err(anyhow!("Can't go from {:?} to {:?}", current, next));
+        }
+        if next == RecorderPhase::Starting {
+            tracked.session_id = None;
+            tracked.elapsed = Duration::ZERO;
+            tracked.chunk_count = 0;
+            tracked.pending_uploads = 0;
+            self.event_count.store(0, Ordering::SeqCst);
+        }
+        if next != RecorderPhase::Error {
+            tracked.error = None;
+        }
+        // Stopwatch runs while recording
+        if next == RecorderPhase::Recording {
+            tracked.recording_since = Some(Instant::now());
+        } else if let Some(since) = tracked.recording_since.take() {
+            tracked.elapsed += since.elapsed();
+        }
+        tracked.phase = next;
+        info!("Recorder {:?} -> {:?}", current, next);
+        self.emit(&tracked);
+        Ok(())
+    }
+
+    /// Enter the error phase, which is allowed from anywhere
+    pub fn fail(&self, error: RecorderError) {
+        self.tracked.lock().unwrap().error = Some(error);
+        _ = self.transition(RecorderPhase::Error);
+    }
+
+    pub fn set_session(&self, session_id: Uuid) {
+        self.tracked.lock().unwrap().session_id = Some(session_id);
+    }
+
+    /// Counter the session increments for every journaled event
+    pub fn event_counter(&self) -> Arc<AtomicU64> {
+        self.event_count.clone()
+    }
+
+    /// Send `upload_progress`, and update the upload counters if it is about the tracked
+    /// session
+    pub fn upload_progress(&self, progress: UploadProgress) {
+        {
+            let mut tracked = self.tracked.lock().unwrap();
+            if tracked.session_id == Some(progress.session_id) {
+                tracked.chunk_count = progress.total;
+                tracked.pending_uploads = progress.total - progress.done;
+            }
+        }
+        if let Err(e) = self.app_handle.emit("upload_progress", progress) {
+            warn!("Failed to emit upload progress: {:?}", e);
+        }
+    }
+
+    /// The uploads of a stopped session are over. Back to idle, unless another recording
+    /// started in the meantime.
+    pub fn finish_uploads(&self, session_id: Uuid, result: Result<()>) {
+        {
+            let tracked = self.tracked.lock().unwrap();
+            if tracked.session_id != Some(session_id) || tracked.phase != RecorderPhase::Uploading {
+                return;
+            }
+        }
+        match result {
+            Ok(()) => _ = self.transition(RecorderPhase::Idle),
+            Err(e) => {
+                warn!("Uploads of session {} failed: {:?}", session_id, e);
+                self.fail(e.into());
+            }
+        }
+    }
+
+    pub fn status(&self) -> RecorderStatus {
+        Self::snapshot(&self.tracked.lock().unwrap(), &self.event_count)
+    }
+
+    fn snapshot(tracked: &Tracked, event_count: &AtomicU64) -> RecorderStatus {
+        let running = tracked
+            .recording_since
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        RecorderStatus {
+            state: tracked.phase,
+            session_id: tracked.session_id,
+            elapsed_ms: (tracked.elapsed + running).as_millis() as u64,
+            event_count: event_count.load(Ordering::SeqCst),
+            chunk_count: tracked.chunk_count,
+            pending_uploads: tracked.pending_uploads,
+            error: tracked.error.clone(),
+        }
+    }
+
+    fn emit(&self, tracked: &Tracked) {
+        let status = Self::snapshot(tracked, &self.event_count);
+        if let Err(e) = self.app_handle.emit("recorder_state_changed", status) {
+            warn!("Failed to emit recorder_state_changed: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::test::{mock_app, MockRuntime};
+
+    use RecorderPhase::*;
+
+    const PHASES: [RecorderPhase; 7] = [
+        Idle, Starting, Recording, Paused, Stopping, Uploading, Error,
+    ];
+
+    fn tracker() -> StatusTracker<MockRuntime> {
+        StatusTracker::new(Arc::new(mock_app().handle().clone()))
+    }
+
+    /// A tracker in `phase`, reached through allowed transitions
+    fn tracker_in(phase: RecorderPhase) -> StatusTracker<MockRuntime> {
+        let path: &[RecorderPhase] = match phase {
+            Idle => &[],
+            Starting => &[Starting],
+            Recording => &[Starting, Recording],
+            Paused => &[Starting, Recording, Paused],
+            Stopping => &[Starting, Recording, Stopping],
+            Uploading => &[Starting, Recording, Stopping, Uploading],
+            Error => &[Error],
+        };
+        let tracker = tracker();
+        for next in path {
+            tracker.transition(*next).unwrap();
+        }
+        assert_eq!(tracker.phase(), phase);
+        tracker
+    }
+
+    #[test]
+    fn transitions() {
+        let allowed = [
+            (Idle, Starting),
+            (Uploading, Starting),
+            (Error, Starting),
+            (Starting, Recording),
+            (Paused, Recording),
+            (Recording, Paused),
+            (Recording, Stopping),
+            (Paused, Stopping),
+            (Stopping, Uploading),
+            (Uploading, Idle),
+            (Error, Idle),
+        ];
+        for from in PHASES {
+            for to in PHASES {
+                let expected = to == Error || allowed.contains(&(from, to));
+                assert_eq!(from.can_become(to), expected, "{:?} -> {:?}", from, to);
+
+                let tracker = tracker_in(from);
+                assert_eq!(tracker.transition(to).is_ok(), expected);
+                // A rejected transition leaves the phase alone
+                let phase = if expected { to } else { from };
+                assert_eq!(tracker.phase(), phase, "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn fail_from_every_phase() {
+        for from in PHASES {
+            let tracker = tracker_in(from);
+            tracker.fail(RecorderError::DiskFull("full".to_string()));
+            let status = tracker.status();
+            assert_eq!(status.state, Error, "{:?}", from);
+            assert_eq!(
+                status.error,
+                Some(RecorderError::DiskFull("full".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn starting_clears_the_error() {
+        let tracker = tracker_in(Error);
+        tracker.fail(RecorderError::NotLoggedIn);
+        tracker.transition(Starting).unwrap();
+        assert_eq!(tracker.status().error, None);
+    }
+
+    #[test]
+    fn finish_uploads_of_the_tracked_session() {
+        let session_id = Uuid::new_v4();
+        let tracker = tracker_in(Starting);
+        tracker.set_session(session_id);
+        for next in [Recording, Stopping, Uploading] {
+            tracker.transition(next).unwrap();
+        }
+
+        tracker.finish_uploads(Uuid::new_v4(), Ok(()));
+        assert_eq!(tracker.phase(), Uploading);
+        tracker.finish_uploads(session_id, Ok(()));
+        assert_eq!(tracker.phase(), Idle);
+    }
+
+    #[test]
+    fn finish_uploads_of_a_stale_session() {
+        let stale = Uuid::new_v4();
+        let tracker = tracker_in(Starting);
+        tracker.set_session(stale);
+        for next in [Recording, Stopping, Uploading, Starting, Recording] {
+            tracker.transition(next).unwrap();
+        }
+        tracker.set_session(Uuid::new_v4());
+
+        // The uploads of the last session don't touch the recording that started since
+        tracker.finish_uploads(stale, Err(anyhow!("offline")));
+        assert_eq!(tracker.phase(), Recording);
+        assert_eq!(tracker.status().error, None);
+    }
+
+    #[test]
+    fn failed_uploads_fail_the_recorder() {
+        let session_id = Uuid::new_v4();
+        let tracker = tracker_in(Starting);
+        tracker.set_session(session_id);
+        for next in [Recording, Stopping, Uploading] {
+            tracker.transition(next).unwrap();
+        }
+        tracker.finish_uploads(session_id, Err(anyhow!("offline")));
+        assert_eq!(tracker.phase(), Error);
+        assert_eq!(
+            tracker.status().error,
+            Some(RecorderError::Other("offline".to_string()))
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::types::PathSample;
+
+/// Longest stretch of cursor motion kept in one trajectory segment before it is flushed
+const MAX_SEGMENT_NANOS: i64 = 1_000_000_000;
+
+/// How cursor motion between other input events is sampled into the trajectory stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum TrajectorySampling {
+    /// Mouse moves are not recorded
+    #[default]
+    Off,
+    /// Keep at most `hz` samples per second
+    FixedRate { hz: u32 },
+    /// Keep a sample once the cursor is at least `min_px` away from the last kept one
+    Distance { min_px: f64 },
+    /// Keep every sample, then simplify each segment with Ramer–Douglas–Peucker
+    Simplify { epsilon_px: f64 },
+}
+
+impl TrajectorySampling {
+    /// Reject rates and distances that would keep every sample or none
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            TrajectorySampling::FixedRate { hz: 0 } => {
+                Err(anyhow!("Sampling rate must be above 0 Hz"))
+            }
+            TrajectorySampling::Distance { min_px } if !(min_px > 0.0 && min_px.is_finite()) => {
+                Err(anyhow!(
+                    "Minimum distance must be above 0 px, got {}",
+                    min_px
+                ))
+            }
+            TrajectorySampling::Simplify { epsilon_px }
+                if !(epsilon_px > 0.0 && epsilon_px.is_finite()) =>
+            {
+                Err(anyhow!("Epsilon must be above 0 px, got {}", epsilon_px))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Collects mouse moves into trajectory segments according to a `TrajectorySampling` mode
+#[derive(Debug)]
+pub struct TrajectorySampler {
+    sampling: TrajectorySampling,
+    segment: Vec<PathSample>,
+}
+
+impl TrajectorySampler {
+    pub fn new(sampling: TrajectorySampling) -> Self {
+        TrajectorySampler {
+            sampling,
+            segment: Vec::new(),
+        }
+    }
+
+    /// Feed a cursor position, returns a finished segment when the current one got too long
+    pub fn push(&mut self, sample: PathSample) -> Option<Vec<PathSample>> {
+        let Some(last) = self.segment.last() else {
+            if self.sampling != TrajectorySampling::Off {
+                self.segment.push(sample);
+            }
+            return None;
+        };
+
+        let keep = match self.sampling {
+            TrajectorySampling::Off => false,
+            TrajectorySampling::FixedRate { hz } => {
+                let interval = 1_000_000_000 / i64::from(hz.max(1));
+                sample.timestamp_nanos - last.timestamp_nanos >= interval
+            }
+            TrajectorySampling::Distance { min_px } => distance(last, &sample) >= min_px,
+            TrajectorySampling::Simplify { .. } => (last.x, last.y) != (sample.x, sample.y),
+        };
+        if !keep {
+            return None;
+        }
+
+        let segment_start = self.segment[0].timestamp_nanos;
+        self.segment.push(sample);
+        if self.segment.last().unwrap().timestamp_nanos - segment_start >= MAX_SEGMENT_NANOS {
+            return self.flush();
+        }
+        None
+    }
+
+    /// Finish the current segment, e.g. because a click or key press is about to be recorded.
+    /// Segments with a single point carry no motion and are dropped.
+    pub fn flush(&mut self) -> Option<Vec<PathSample>> {
+        let segment = std::mem::take(&mut self.segment);
+        if segment.len() < 2 {
+            return None;
+        }
+        match self.sampling {
+            TrajectorySampling::Simplify { epsilon_px } => Some(simplify(&segment, epsilon_px)),
+            _ => Some(segment),
+        }
+    }
+}
+
+fn distance(a: &PathSample, b: &PathSample) -> f64 {
+    f64::from(b.x - a.x).hypot(f64::from(b.y - a.y))
+}
+
+/// Distance from `point` to the line through `start` and `end`
+fn perpendicular_distance(point: &PathSample, start: &PathSample, end: &PathSample) -> f64 {
+    let (dx, dy) = (f64::from(end.x - start.x), f64::from(end.y - start.y));
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return distance(start, point);
+    }
+    (dy * f64::from(point.x - start.x) - dx * f64::from(point.y - start.y)).abs() / length
+}
+
+/// Ramer–Douglas–Peucker simplification, keeps the first and last point of the path
+fn simplify(path: &[PathSample], epsilon: f64) -> Vec<PathSample> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let mut keep = vec![false; path.len()];
+    keep[0] = true;
+    keep[path.len() - 1] = true;
+
+    // Iterative to avoid deep recursion on long, noisy segments
+    let mut ranges = vec![(0, path.len() - 1)];
+    while let Some((start, end)) = ranges.pop() {
+        let farthest = (start + 1..end)
+            .map(|i| {
+                (
+                    i,
+                    perpendicular_distance(&path[i], &path[start], &path[end]),
+                )
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((index, dist)) = farthest {
+            if dist > epsilon {
+                keep[index] = true;
+                ranges.push((start, index));
+                ranges.push((index, end));
+            }
+        }
+    }
+
+    path.iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(sample, _)| sample.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MILLI: i64 = 1_000_000;
+
+    fn sample(x: i32, y: i32, millis: i64) -> PathSample {
+        PathSample {
+            x,
+            y,
+            timestamp_nanos: millis * MILLI,
+        }
+    }
+
+    fn points(path: &[PathSample]) -> Vec<(i32, i32)> {
+        path.iter().map(|sample| (sample.x, sample.y)).collect()
+    }
+
+    /// Feed `samples` and flush, collecting every finished segment
+    fn sampled(sampling: TrajectorySampling, samples: &[PathSample]) -> Vec<Vec<(i32, i32)>> {
+        let mut sampler = TrajectorySampler::new(sampling);
+        let mut segments: Vec<_> = samples
+            .iter()
+            .filter_map(|sample| sampler.push(sample.clone()))
+            .collect();
+        segments.extend(sampler.flush());
+        segments.iter().map(|segment| points(segment)).collect()
+    }
+
+    #[test]
+    fn simplify_straight_line() {
+        let line: Vec<_> = (0..10)
+            .map(|i| sample(i * 10, i * 5, i64::from(i)))
+            .collect();
+        assert_eq!(points(&simplify(&line, 0.5)), [(0, 0), (90, 45)]);
+    }
+
+    #[test]
+    fn simplify_keeps_corners() {
+        let path = [
+            sample(0, 0, 0),
+            sample(5, 1, 1),
+            sample(10, 0, 2),
+            sample(10, 10, 3),
+            sample(11, 20, 4),
+            sample(10, 30, 5),
+        ];
+        assert_eq!(points(&simplify(&path, 2.0)), [(0, 0), (10, 0), (10, 30)]);
+        // A smaller epsilon keeps the wobbles too
+        assert_eq!(points(&simplify(&path, 0.25)), points(&path));
+    }
+
+    #[test]
+    fn simplify_drops_points_at_epsilon() {
+        let path = [sample(0, 0, 0), sample(5, 3, 1), sample(10, 0, 2)];
+        assert_eq!(points(&simplify(&path, 3.0)), [(0, 0), (10, 0)]);
+        assert_eq!(points(&simplify(&path, 2.9)), [(0, 0), (5, 3), (10, 0)]);
+    }
+
+    #[test]
+    fn simplify_closed_loop() {
+        // Start and end coincide, distances are measured from that point
+        let path = [
+            sample(0, 0, 0),
+            sample(10, 0, 1),
+            sample(10, 10, 2),
+            sample(0, 0, 3),
+        ];
+        assert_eq!(points(&simplify(&path, 1.0)), points(&path));
+        assert_eq!(points(&simplify(&path, 8.0)), [(0, 0), (10, 10), (0, 0)]);
+    }
+
+    #[test]
+    fn simplify_short_paths() {
+        assert!(simplify(&[], 1.0).is_empty());
+        let path = [sample(0, 0, 0), sample(1, 1, 1)];
+        assert_eq!(points(&simplify(&path, 1.0)), points(&path));
+    }
+
+    #[test]
+    fn off_keeps_nothing() {
+        let samples: Vec<_> = (0..10)
+            .map(|i| sample(i * 10, 0, i64::from(i) * 10))
+            .collect();
+        assert!(sampled(TrajectorySampling::Off, &samples).is_empty());
+    }
+
+    #[test]
+    fn fixed_rate_mode() {
+        // 100 Hz keeps a sample every 10ms
+        let samples: Vec<_> = (0..10).map(|i| sample(i, 0, i64::from(i) * 4)).collect();
+        assert_eq!(
+            sampled(TrajectorySampling::FixedRate { hz: 100 }, &samples),
+            [[(0, 0), (3, 0), (6, 0), (9, 0)]]
+        );
+    }
+
+    #[test]
+    fn distance_mode() {
+        let samples = [
+            sample(0, 0, 0),
+            sample(3, 4, 1),
+            sample(4, 4, 2),
+            sample(6, 8, 3),
+            sample(8, 12, 4),
+            sample(9, 12, 5),
+        ];
+        assert_eq!(
+            sampled(TrajectorySampling::Distance { min_px: 5.0 }, &samples),
+            [[(0, 0), (3, 4), (6, 8), (9, 12)]]
+        );
+    }
+
+    #[test]
+    fn simplify_mode() {
+        let samples = [
+            sample(0, 0, 0),
+            sample(0, 0, 1),
+            sample(5, 0, 2),
+            sample(10, 0, 3),
+            sample(10, 5, 4),
+            sample(10, 10, 5),
+        ];
+        assert_eq!(
+            sampled(TrajectorySampling::Simplify { epsilon_px: 1.0 }, &samples),
+            [[(0, 0), (10, 0), (10, 10)]]
+        );
+    }
+
+    #[test]
+    fn long_segments_are_split() {
+        let samples: Vec<_> = (0..=15).map(|i| sample(i, 0, i64::from(i) * 100)).collect();
+        let segments = sampled(TrajectorySampling::Distance { min_px: 1.0 }, &samples);
+        assert_eq!(segments.len(), 2);
+        // The first segment ends once it spans a second
+        assert_eq!(segments[0].len(), 11);
+        assert_eq!(segments[0].last(), Some(&(10, 0)));
+        assert_eq!(segments[1].first(), Some(&(11, 0)));
+    }
+
+    #[test]
+    fn single_points_are_dropped() {
+        let samples = [sample(0, 0, 0), sample(1, 0, 1)];
+        assert!(sampled(TrajectorySampling::Distance { min_px: 5.0 }, &samples).is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_zero_and_negative_values() {
+        let valid = [
+            TrajectorySampling::Off,
+            TrajectorySampling::FixedRate { hz: 60 },
+            TrajectorySampling::Distance { min_px: 0.5 },
+            TrajectorySampling::Simplify { epsilon_px: 2.0 },
+        ];
+        for sampling in valid {
+            assert!(sampling.validate().is_ok(), "{:?}", sampling);
+        }
+        let invalid = [
+            TrajectorySampling::FixedRate { hz: 0 },
+            TrajectorySampling::Distance { min_px: 0.0 },
+            TrajectorySampling::Distance { min_px: -1.0 },
+            TrajectorySampling::Distance { min_px: f64::NAN },
+            TrajectorySampling::Simplify { epsilon_px: 0.0 },
+            TrajectorySampling::Simplify { epsilon_px: -2.0 },
+            TrajectorySampling::Simplify {
+                epsilon_px: f64::INFINITY,
+            },
+        ];
+        for sampling in invalid {
+            assert!(sampling.validate().is_err(), "{:?}", sampling);
+        }
+    }
+}
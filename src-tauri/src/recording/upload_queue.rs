@@ -0,0 +1,415 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::segments::{chunk_files, chunk_index};
+use crate::files::write_atomic;
+
+/// Upload state of every chunk of a session, kept in the session directory
+pub const UPLOAD_QUEUE_FILE: &str = "uploads.json";
+/// Where a queue file that can't be parsed is moved, kept for a look at what went wrong
+const CORRUPT_QUEUE_FILE: &str = "uploads.json.corrupt";
+
+/// Attempts per chunk before it is marked failed. Failed chunks get another round of attempts
+/// the next time the app starts.
+const MAX_ATTEMPTS: u32 = 8;
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum UploadState {
+    Pending,
+    Uploading,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkUpload {
+    pub file_name: String,
+    /// Assigned when the chunk is queued and sent with every attempt
+    pub recording_id: Uuid,
+    pub index: u32,
+    pub state: UploadState,
+    pub attempts: u32,
+    /// Earliest time of the next attempt, unix millis
+    pub retry_at_millis: i64,
+    pub last_error: Option<String>,
+}
+
+/// Payload of the `upload_progress` event, sent whenever a chunk changes state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub session_id: Uuid,
+    pub chunk: ChunkUpload,
+    pub done: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+#[derive(Debug)]
+pub struct UploadQueue {
+    path: PathBuf,
+    chunks: Vec<ChunkUpload>,
+}
+
+impl UploadQueue {
+    /// Load the queue of a session, or start an empty one. Uploads that were in flight when the
+    /// app stopped, and chunks that ran out of attempts, are pending again. A corrupt queue is
+    /// rebuilt from the chunk files.
+    pub fn load(session_dir: &Path) -> Result<Self> {
+        let path = session_dir.join(UPLOAD_QUEUE_FILE);
+        let mut chunks: Vec<ChunkUpload> = match fs::read(&path) {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    warn!("Upload queue {:?} is corrupt, rebuilding it: {}", path, e);
+                    return UploadQueue::rebuild(session_dir);
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context("Failed to read upload queue"),
+        };
+        for chunk in chunks.iter_mut() {
+            if matches!(chunk.state, UploadState::Uploading | UploadState::Failed) {
+                chunk.state = UploadState::Pending;
+                chunk.attempts = 0;
+                chunk.retry_at_millis = 0;
+            }
+        }
+        Ok(UploadQueue { path, chunks })
+    }
+
+    /// Set a corrupt queue file aside and queue every chunk of the session again. Which chunks
+    /// were uploaded is lost with the file, so they are all uploaded again.
+    fn rebuild(session_dir: &Path) -> Result<Self> {
+        let path = session_dir.join(UPLOAD_QUEUE_FILE);
+        fs::rename(&path, session_dir.join(CORRUPT_QUEUE_FILE))
+            .context("Failed to set aside corrupt upload queue")?;
+        let mut queue = UploadQueue {
+            path,
+            chunks: Vec::new(),
+        };
+        for chunk in chunk_files(&session_dir.join("recordings")) {
+            queue.enqueue(&chunk)?;
+        }
+        queue.save()?;
+        Ok(queue)
+    }
+
+    /// Whether a session has chunks that aren't uploaded yet. A queue that can't be read counts
+    /// as unfinished, so recovery gets to report why.
+    pub fn has_unfinished(session_dir: &Path) -> bool {
+        match UploadQueue::load(session_dir) {
+            Ok(queue) => !queue.is_done(),
+            Err(e) => {
+                warn!("Failed to load upload queue of {:?}: {:?}", session_dir, e);
+                true
+            }
+        }
+    }
+
+    /// Add a chunk, unless it is already queued
+    pub fn enqueue(&mut self, file_name: &str) -> Result<()> {
+        if self.chunks.iter().any(|chunk| chunk.file_name == file_name) {
+            return Ok(());
+        }
+        let index =
+            chunk_index(file_name).with_context(|| format!("Unexpected chunk {}", file_name))?;
+        self.chunks.push(ChunkUpload {
+            file_name: file_name.to_string(),
+            recording_id: Uuid::new_v4(),
+            index,
+            state: UploadState::Pending,
+            attempts: 0,
+            retry_at_millis: 0,
+            last_error: None,
+        });
+        self.save()
+    }
+
+    /// Upload a chunk again, even if it was uploaded before, e.g. because it was repaired
+    pub fn requeue(&mut self, file_name: &str) -> Result<()> {
+        match self
+            .chunks
+            .iter_mut()
+            .find(|chunk| chunk.file_name == file_name)
+        {
+            Some(chunk) => {
+                chunk.state = UploadState::Pending;
+                chunk.attempts = 0;
+                chunk.retry_at_millis = 0;
+                self.save()
+            }
+            None => self.enqueue(file_name),
+        }
+    }
+
+    /// Whether a chunk was uploaded completely
+    pub fn was_uploaded(&self, file_name: &str) -> bool {
+        self.chunks
+            .iter()
+            .any(|chunk| chunk.file_name == file_name && chunk.state == UploadState::Done)
+    }
+
+    /// Claim the oldest pending chunk whose backoff has passed
+    pub fn start_next(&mut self) -> Result<Option<ChunkUpload>> {
+        let now = Utc::now().timestamp_millis();
+        let Some(chunk) = self
+            .chunks
+            .iter_mut()
+            .find(|chunk| chunk.state == UploadState::Pending && chunk.retry_at_millis <= now)
+        else {
+            return Ok(None);
+        };
+        chunk.state = UploadState::Uploading;
+        chunk.attempts += 1;
+        let chunk = chunk.clone();
+        self.save()?;
+        Ok(Some(chunk))
+    }
+
+    pub fn finish(&mut self, file_name: &str) -> Result<ChunkUpload> {
+        self.update(file_name, |chunk| {
+            chunk.state = UploadState::Done;
+            chunk.last_error = None;
+        })
+    }
+
+    /// Schedule a retry with exponential backoff and jitter, or give up after `MAX_ATTEMPTS`
+    pub fn fail(&mut self, file_name: &str, error: &str) -> Result<ChunkUpload> {
+        self.update(file_name, |chunk| {
+            chunk.last_error = Some(error.to_string());
+            if chunk.attempts >= MAX_ATTEMPTS {
+                chunk.state = UploadState::Failed;
+                return;
+            }
+            chunk.state = UploadState::Pending;
+            chunk.retry_at_millis = Utc::now().timestamp_millis() + backoff(chunk.attempts);
+        })
+    }
+
+    /// True when every chunk is uploaded
+    pub fn is_done(&self) -> bool {
+        self.all_in(&[UploadState::Done])
+    }
+
+    /// True when nothing is pending or uploading
+    pub fn is_settled(&self) -> bool {
+        self.all_in(&[UploadState::Done, UploadState::Failed])
+    }
+
+    pub fn progress(&self, session_id: Uuid, chunk: ChunkUpload) -> UploadProgress {
+        let count = |state| {
+            self.chunks
+                .iter()
+                .filter(|chunk| chunk.state == state)
+                .count()
+        };
+        UploadProgress {
+            session_id,
+            chunk,
+            done: count(UploadState::Done),
+            failed: count(UploadState::Failed),
+            total: self.chunks.len(),
+        }
+    }
+
+    /// Whether every chunk is in one of `states`
+    fn all_in(&self, states: &[UploadState]) -> bool {
+        self.chunks
+            .iter()
+            .all(|chunk| states.contains(&chunk.state))
+    }
+
+    fn update(&mut self, file_name: &str, f: impl FnOnce(&mut ChunkUpload)) -> Result<ChunkUpload> {
+        let chunk = self
+            .chunks
+            .iter_mut()
+            .find(|chunk| chunk.file_name == file_name)
+            .with_context(|| format!("{} is not queued", file_name))?;
+        f(chunk);
+        let chunk = chunk.clone();
+        self.save()?;
+        Ok(chunk)
+    }
+
+    /// Replace the queue file atomically, so a crash leaves either the old or the new state
+    fn save(&self) -> Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&self.chunks)?, false)
+            .context("Failed to write upload queue")
+    }
+}
+
+/// Delay before the next attempt in millis: exponential in the attempts so far, capped, and
+/// jittered over its upper half so retries of many chunks don't line up
+fn backoff(attempts: u32) -> i64 {
+    let delay = BASE_BACKOFF
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF)
+        .as_millis() as i64;
+    rand::thread_rng().gen_range(delay / 2..=delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue_of(session_dir: &Path, chunks: &[&str]) -> UploadQueue {
+        let mut queue = UploadQueue::load(session_dir).unwrap();
+        for chunk in chunks {
+            queue.enqueue(chunk).unwrap();
+        }
+        queue
+    }
+
+    /// Claim `file_name` as soon as its backoff would allow
+    fn start(queue: &mut UploadQueue, file_name: &str) -> ChunkUpload {
+        for chunk in queue.chunks.iter_mut() {
+            chunk.retry_at_millis = 0;
+        }
+        let chunk = queue.start_next().unwrap().unwrap();
+        assert_eq!(chunk.file_name, file_name);
+        chunk
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        for attempts in 0..40u32 {
+            let expected = BASE_BACKOFF
+                .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+                .min(MAX_BACKOFF)
+                .as_millis() as i64;
+            for _ in 0..100 {
+                let delay = backoff(attempts);
+                // Jitter stays in the upper half
+                assert!(
+                    (expected / 2..=expected).contains(&delay),
+                    "{} attempts: {} ms",
+                    attempts,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fail_schedules_a_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut queue = queue_of(dir.path(), &["chunk_0000.mkv"]);
+        start(&mut queue, "chunk_0000.mkv");
+
+        let before = Utc::now().timestamp_millis();
+        let chunk = queue.fail("chunk_0000.mkv", "timed out").unwrap();
+        let after = Utc::now().timestamp_millis();
+        assert_eq!(chunk.state, UploadState::Pending);
+        assert_eq!(chunk.last_error.as_deref(), Some("timed out"));
+        let delay = BASE_BACKOFF.as_millis() as i64;
+        assert!(chunk.retry_at_millis >= before + delay / 2);
+        assert!(chunk.retry_at_millis <= after + delay);
+        // Not due yet
+        assert!(queue.start_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut queue = queue_of(dir.path(), &["chunk_0000.mkv"]);
+        for attempt in 1..=MAX_ATTEMPTS {
+            assert_eq!(start(&mut queue, "chunk_0000.mkv").attempts, attempt);
+            let chunk = queue.fail("chunk_0000.mkv", "timed out").unwrap();
+            let state = if attempt < MAX_ATTEMPTS {
+                UploadState::Pending
+            } else {
+                UploadState::Failed
+            };
+            assert_eq!(chunk.state, state);
+        }
+        assert!(queue.start_next().unwrap().is_none());
+        assert!(queue.is_settled());
+        assert!(!queue.is_done());
+    }
+
+    #[test]
+    fn reload_keeps_uploads_and_retries_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let chunks = ["chunk_0000.mkv", "chunk_0001.mkv", "chunk_0002.mkv"];
+        let mut queue = queue_of(dir.path(), &chunks);
+        start(&mut queue, "chunk_0000.mkv");
+        queue.finish("chunk_0000.mkv").unwrap();
+        start(&mut queue, "chunk_0001.mkv");
+        queue.fail("chunk_0001.mkv", "timed out").unwrap();
+        start(&mut queue, "chunk_0001.mkv");
+        // chunk_0001 is in flight and chunk_0002 ran out of attempts when the app stops
+        queue.chunks[2].state = UploadState::Failed;
+        queue.chunks[2].attempts = MAX_ATTEMPTS;
+        queue.save().unwrap();
+
+        let reloaded = UploadQueue::load(dir.path()).unwrap();
+        assert_eq!(reloaded.chunks.len(), 3);
+        for (before, after) in queue.chunks.iter().zip(&reloaded.chunks) {
+            assert_eq!(after.file_name, before.file_name);
+            assert_eq!(after.recording_id, before.recording_id);
+            assert_eq!(after.index, before.index);
+        }
+        assert!(reloaded.was_uploaded("chunk_0000.mkv"));
+        for chunk in &reloaded.chunks[1..] {
+            assert_eq!(chunk.state, UploadState::Pending);
+            assert_eq!(chunk.attempts, 0);
+            assert_eq!(chunk.retry_at_millis, 0);
+        }
+        assert_eq!(reloaded.chunks[1].last_error.as_deref(), Some("timed out"));
+    }
+
+    #[test]
+    fn enqueue_ignores_queued_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut queue = queue_of(dir.path(), &["chunk_0003.mkv"]);
+        let recording_id = queue.chunks[0].recording_id;
+        queue.enqueue("chunk_0003.mkv").unwrap();
+        assert_eq!(queue.chunks.len(), 1);
+        assert_eq!(queue.chunks[0].recording_id, recording_id);
+        assert_eq!(queue.chunks[0].index, 3);
+        assert!(queue.enqueue("segments.csv").is_err());
+    }
+
+    #[test]
+    fn corrupt_queue_is_rebuilt_from_the_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let recordings_dir = dir.path().join("recordings");
+        fs::create_dir(&recordings_dir).unwrap();
+        for chunk in ["chunk_0000.mkv", "chunk_0001.mkv"] {
+            fs::write(recordings_dir.join(chunk), "").unwrap();
+        }
+        fs::write(dir.path().join(UPLOAD_QUEUE_FILE), "[{\"file_name\":").unwrap();
+
+        assert!(UploadQueue::has_unfinished(dir.path()));
+        assert!(dir.path().join(CORRUPT_QUEUE_FILE).exists());
+        let queue = UploadQueue::load(dir.path()).unwrap();
+        let file_names: Vec<&str> = queue
+            .chunks
+            .iter()
+            .map(|chunk| chunk.file_name.as_str())
+            .collect();
+        assert_eq!(file_names, ["chunk_0000.mkv", "chunk_0001.mkv"]);
+        assert!(queue
+            .chunks
+            .iter()
+            .all(|chunk| chunk.state == UploadState::Pending));
+    }
+
+    #[test]
+    fn missing_queue_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!UploadQueue::has_unfinished(dir.path()));
+        assert!(UploadQueue::load(dir.path()).unwrap().is_done());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use super::error::RecorderError;
+
+/// FFmpeg without a progress report for this long is considered hung. Reports come every half
+/// second while frames are read, also when `mpdecimate` drops them all.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+/// Lines of FFmpeg's log kept to tell why it exited
+const LOG_TAIL: usize = 20;
+/// Restarts in a row, each after a short run, before the recording is given up
+const MAX_RESTARTS: u32 = 3;
+/// A run that lasted this long ends a streak of restarts
+const HEALTHY_RUN: Duration = Duration::from_secs(30);
+/// Pause before restarting, e.g. for a display that is being reconnected
+pub const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Why an FFmpeg run ended without the recorder asking it to
+#[derive(Debug)]
+pub enum RunFailure {
+    Exited(ExitStatus),
+    Stalled,
+}
+
+impl fmt::Display for RunFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunFailure::Exited(status) => write!(f, "exited with {}", status),
+            RunFailure::Stalled => write!(f, "stalled"),
+        }
+    }
+}
+
+/// What the reader threads saw of one FFmpeg run
+pub struct FfmpegWatchdog {
+    started: Instant,
+    last_progress: Mutex<Instant>,
+    /// Wall clock time of the last progress report, where the video stops in a gap
+    last_progress_nanos: AtomicI64,
+    frames: AtomicU64,
+    log_tail: Mutex<VecDeque<String>>,
+}
+
+impl FfmpegWatchdog {
+    pub fn new() -> Self {
+        FfmpegWatchdog {
+            started: Instant::now(),
+            last_progress: Mutex::new(Instant::now()),
+            last_progress_nanos: AtomicI64::new(
+                Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            ),
+            frames: AtomicU64::new(0),
+            log_tail: Mutex::new(VecDeque::with_capacity(LOG_TAIL)),
+        }
+    }
+
+    /// Take a line of `-progress` output, `key=value` pairs in blocks closed by `progress=`
+    pub fn observe_progress(&self, line: &str) {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            return;
+        };
+        match key {
+            "frame" => {
+                if let Ok(frames) = value.parse() {
+                    self.frames.store(frames, Ordering::SeqCst);
+                }
+            }
+            "progress" => {
+                *self.last_progress.lock().unwrap() = Instant::now();
+                self.last_progress_nanos.store(
+                    Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+                    Ordering::SeqCst,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Take a line of FFmpeg's log
+    pub fn observe_log(&self, line: String) {
+        let mut log_tail = self.log_tail.lock().unwrap();
+        if log_tail.len() == LOG_TAIL {
+            log_tail.pop_front();
+        }
+        log_tail.push_back(line);
+    }
+
+    pub fn stalled(&self) -> bool {
+        self.last_progress.lock().unwrap().elapsed() > STALL_TIMEOUT
+    }
+
+    /// Frames written in this run
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::SeqCst)
+    }
+
+    /// When FFmpeg was last seen working, or started if it never reported
+    pub fn last_alive_nanos(&self) -> i64 {
+        self.last_progress_nanos.load(Ordering::SeqCst)
+    }
+
+    /// When the run started
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Describe a failed run, read once the reader threads are done so the log is complete
+    pub fn error(&self, failure: &RunFailure) -> RecorderError {
+        match failure {
+            RunFailure::Exited(_) => {
+                let log_tail: Vec<String> = self.log_tail.lock().unwrap().iter().cloned().collect();
+                RecorderError::from_ffmpeg_log(&log_tail)
+            }
+            RunFailure::Stalled => RecorderError::FfmpegExited(format!(
+                "No progress for {} seconds",
+                STALL_TIMEOUT.as_secs()
+            )),
+        }
+    }
+}
+
+/// Decides whether a failed FFmpeg run is restarted or ends the recording
+#[derive(Debug, Default)]
+pub struct RestartPolicy {
+    restarts: u32,
+}
+
+impl RestartPolicy {
+    /// Count a run that started at `started` and failed at `failed_at`. Restarting doesn't help
+    /// when recording isn't allowed or the disk is full, nor when FFmpeg keeps failing right
+    /// after it starts.
+    pub fn allow_restart(
+        &mut self,
+        started: Instant,
+        failed_at: Instant,
+        error: &RecorderError,
+    ) -> bool {
+        if matches!(
+            error,
+            RecorderError::PermissionDenied(_) | RecorderError::DiskFull(_)
+        ) {
+            return false;
+        }
+        if failed_at.saturating_duration_since(started) >= HEALTHY_RUN {
+            self.restarts = 0;
+        }
+        self.restarts += 1;
+        self.restarts <= MAX_RESTARTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crash() -> RecorderError {
+        RecorderError::FfmpegExited("Conversion failed!".to_string())
+    }
+
+    /// Fail a run of `ran_for` that starts at `*now`, and move `*now` past it
+    fn fail_run(policy: &mut RestartPolicy, now: &mut Instant, ran_for: Duration) -> bool {
+        let started = *now;
+        *now += ran_for;
+        policy.allow_restart(started, *now, &crash())
+    }
+
+    #[test]
+    fn short_runs_use_up_the_restarts() {
+        let mut policy = RestartPolicy::default();
+        let mut now = Instant::now();
+        for _ in 0..MAX_RESTARTS {
+            assert!(fail_run(&mut policy, &mut now, Duration::from_secs(1)));
+        }
+        assert!(!fail_run(&mut policy, &mut now, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn healthy_run_resets_the_restarts() {
+        let mut policy = RestartPolicy::default();
+        let mut now = Instant::now();
+        for _ in 0..MAX_RESTARTS {
+            assert!(fail_run(&mut policy, &mut now, Duration::from_secs(1)));
+        }
+        assert!(fail_run(&mut policy, &mut now, HEALTHY_RUN));
+        for _ in 1..MAX_RESTARTS {
+            assert!(fail_run(&mut policy, &mut now, Duration::from_secs(1)));
+        }
+        assert!(!fail_run(&mut policy, &mut now, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn run_just_short_of_healthy_counts() {
+        let mut policy = RestartPolicy::default();
+        let mut now = Instant::now();
+        let almost_healthy = HEALTHY_RUN - Duration::from_millis(1);
+        for _ in 0..MAX_RESTARTS {
+            assert!(fail_run(&mut policy, &mut now, almost_healthy));
+        }
+        assert!(!fail_run(&mut policy, &mut now, almost_healthy));
+    }
+
+    #[test]
+    fn hopeless_errors_are_not_restarted() {
+        let now = Instant::now();
+        let later = now + HEALTHY_RUN;
+        for error in [
+            RecorderError::PermissionDenied("Permission denied".to_string()),
+            RecorderError::DiskFull("No space left on device".to_string()),
+        ] {
+            let mut policy = RestartPolicy::default();
+            assert!(!policy.allow_restart(now, later, &error));
+        }
+    }
+}
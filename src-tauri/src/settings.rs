@@ -0,0 +1,394 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::auth::logout;
+use crate::files::write_atomic;
+use crate::recording::CaptureConfig;
+
+/// Settings file in the app config dir
+pub const SETTINGS_FILE: &str = "settings.json";
+/// Profile to use regardless of the settings file, e.g. `GHOST_API_PROFILE=local`
+pub const API_PROFILE_ENV: &str = "GHOST_API_PROFILE";
+/// Base URL to use regardless of profile, e.g. a mock server
+pub const API_URL_ENV: &str = "GHOST_API_URL";
+
+/// Backend the app talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum ApiProfile {
+    #[default]
+    Prod,
+    Staging,
+    Local,
+}
+
+impl ApiProfile {
+    /// Built-in URL of the profile. Staging has none, its URL is set with `api_base_url` or
+    /// `GHOST_API_URL`.
+    pub fn base_url(self) -> Option<&'static str> {
+        match self {
+            ApiProfile::Prod => Some("https://echo.i.inc"),
+            ApiProfile::Staging => None,
+            ApiProfile::Local => Some("http://localhost:8000"),
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_lowercase())).ok()
+    }
+}
+
+/// Paths of the backend's auth endpoints, relative to its base URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthPaths {
+    /// Login page opened in the browser. Called with `redirect_uri`, it logs the user in and
+    /// redirects there with the JWT in `token`.
+    pub login: String,
+    /// Authenticated `POST` that answers `{"token": "<new JWT>"}`
+    pub refresh: String,
+}
+
+impl Default for AuthPaths {
+    fn default() -> Self {
+        AuthPaths {
+            login: "/auth/login".to_string(),
+            refresh: "/auth/refresh".to_string(),
+        }
+    }
+}
+
+/// Contents of the settings file. Missing fields take their defaults, so older files keep
+/// loading as settings are added.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub api_profile: ApiProfile,
+    /// Replaces the base URL of the profile, required for staging
+    pub api_base_url: Option<String>,
+    pub auth_paths: AuthPaths,
+    pub capture: CaptureConfig,
+}
+
+/// The backend in use and where that choice came from, for the UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiSettings {
+    pub profile: ApiProfile,
+    pub base_url: String,
+    /// Set by `GHOST_API_PROFILE` or `GHOST_API_URL`, changing the profile has no effect
+    pub from_env: bool,
+}
+
+/// The backend chosen with `GHOST_API_PROFILE` and `GHOST_API_URL`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApiEnv {
+    pub profile: Option<ApiProfile>,
+    pub url: Option<String>,
+}
+
+impl ApiEnv {
+    /// Read the variables at startup
+    fn from_env() -> Self {
+        ApiEnv::parse(
+            std::env::var(API_PROFILE_ENV).ok(),
+            std::env::var(API_URL_ENV).ok(),
+        )
+    }
+
+    /// Ignore an empty URL, and a profile that is unknown or has no URL to go with it
+    fn parse(profile: Option<String>, url: Option<String>) -> Self {
+        let url = url.filter(|url| !url.is_empty());
+        let profile = profile.and_then(|name| {
+            let profile = ApiProfile::parse(&name);
+            if profile.is_none() {
+                warn!("Ignoring unknown {}={}", API_PROFILE_ENV, name);
+            }
+            // Without a URL of its own, the profile needs one from the environment too
+            profile.filter(|profile| {
+                let has_url = profile.base_url().is_some() || url.is_some();
+                if !has_url {
+                    warn!(
+                        "Ignoring {}={} without {}",
+                        API_PROFILE_ENV, name, API_URL_ENV
+                    );
+                }
+                has_url
+            })
+        });
+        ApiEnv { profile, url }
+    }
+}
+
+/// The backend `settings` would use under `env`, none for a profile without a URL. The
+/// environment's URL beats any profile's, the environment's profile beats the settings file,
+/// and the settings file's URL beats its profile's own.
+pub fn resolve_api(settings: &Settings, env: &ApiEnv) -> Option<ApiSettings> {
+    let profile = env.profile.unwrap_or(settings.api_profile);
+    let base_url = match (&env.url, env.profile) {
+        (Some(url), _) => url.clone(),
+        (None, Some(profile)) => profile.base_url()?.to_string(),
+        (None, None) => match &settings.api_base_url {
+            Some(url) => url.clone(),
+            None => profile.base_url()?.to_string(),
+        },
+    };
+    Some(ApiSettings {
+        profile,
+        base_url: base_url.trim_end_matches('/').to_string(),
+        from_env: env.profile.is_some() || env.url.is_some(),
+    })
+}
+
+/// Managed state with the settings loaded at startup
+pub struct SettingsState {
+    path: PathBuf,
+    settings: Mutex<Settings>,
+    env: ApiEnv,
+}
+
+impl SettingsState {
+    /// Load the settings file in `config_dir`, starting from the defaults if there is none or
+    /// it can't be read
+    pub fn load(config_dir: &Path) -> Self {
+        let path = config_dir.join(SETTINGS_FILE);
+        let mut settings = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                warn!("Invalid settings file, using defaults: {:?}", e);
+                Settings::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Settings::default(),
+            Err(e) => {
+                warn!("Failed to read settings, using defaults: {:?}", e);
+                Settings::default()
+            }
+        };
+        if settings.api_profile.base_url().is_none() && settings.api_base_url.is_none() {
+            warn!("No URL for the {:?} API, using prod", settings.api_profile);
+            settings.api_profile = ApiProfile::Prod;
+        }
+        let state = SettingsState {
+            path,
+            settings: Mutex::new(settings),
+            env: ApiEnv::from_env(),
+        };
+        let api = state.api();
+        info!("Using the {:?} API at {}", api.profile, api.base_url);
+        state
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Change the settings and write them to the settings file. They only change in memory
+    /// once they are written, so a failed write leaves both as they were.
+    pub fn update(&self, f: impl FnOnce(&mut Settings)) -> Result<Settings> {
+        let mut settings = self.settings.lock().unwrap();
+        let mut updated = settings.clone();
+        f(&mut updated);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create config dir")?;
+        }
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&updated)?, false)
+            .context("Failed to write settings")?;
+        *settings = updated.clone();
+        Ok(updated)
+    }
+
+    /// The backend to use: the environment overrides the settings file, which overrides the
+    /// prod default
+    pub fn api(&self) -> ApiSettings {
+        // `load` and `set_api_profile` only keep settings that have a URL
+        resolve_api(&self.get(), &self.env).unwrap_or_else(|| ApiSettings {
+            profile: ApiProfile::Prod,
+            base_url: ApiProfile::Prod.base_url().unwrap_or_default().to_string(),
+            from_env: false,
+        })
+    }
+}
+
+/// Base URL of the backend in use, without a trailing slash
+pub fn api_base_url(app: &AppHandle) -> String {
+    app.state::<SettingsState>().api().base_url
+}
+
+/// Auth endpoints of the backend in use
+pub fn auth_paths(app: &AppHandle) -> AuthPaths {
+    app.state::<SettingsState>().get().auth_paths
+}
+
+#[tauri::command]
+pub fn get_api_settings(state: State<'_, SettingsState>) -> ApiSettings {
+    state.api()
+}
+
+/// Switch the backend, at `base_url` instead of the profile's own URL if given. Staging has
+/// no URL of its own, so it needs one. Tokens are only valid for the backend that issued them,
+/// so switching to another URL logs out.
+#[tauri::command]
+pub fn set_api_profile(
+    app: AppHandle,
+    state: State<'_, SettingsState>,
+    profile: ApiProfile,
+    base_url: Option<String>,
+) -> Result<ApiSettings, String> {
+    let base_url = base_url.filter(|url| !url.trim().is_empty());
+    let mut settings = state.get();
+    settings.api_profile = profile;
+    settings.api_base_url = base_url.clone();
+    if resolve_api(&settings, &state.env).is_none() {
+        return Err(format!("The {:?} API needs a base URL", profile));
+    }
+
+    let previous = state.api();
+    state
+        .update(|settings| {
+            settings.api_profile = profile;
+            settings.api_base_url = base_url;
+        })
+        .map_err(|e| e.to_string())?;
+
+    let api = state.api();
+    if api.base_url != previous.base_url {
+        info!("Switched to the {:?} API at {}", api.profile, api.base_url);
+        logout(app)?;
+    }
+    Ok(api)
+}
+
+#[tauri::command]
+pub fn get_capture_config(state: State<'_, SettingsState>) -> CaptureConfig {
+    state.get().capture
+}
+
+/// Change how the screen is encoded, from the next recording session
+#[tauri::command]
+pub fn set_capture_config(
+    state: State<'_, SettingsState>,
+    config: CaptureConfig,
+) -> Result<CaptureConfig, String> {
+    config.validate().map_err(|e| e.to_string())?;
+    let settings = state
+        .update(|settings| settings.capture = config)
+        .map_err(|e| e.to_string())?;
+    info!("Capture settings changed to {:?}", settings.capture);
+    Ok(settings.capture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUSTOM_URL: &str = "https://staging.example.com";
+
+    fn settings(api_profile: ApiProfile, api_base_url: Option<&str>) -> Settings {
+        Settings {
+            api_profile,
+            api_base_url: api_base_url.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn env(profile: Option<&str>, url: Option<&str>) -> ApiEnv {
+        ApiEnv::parse(profile.map(String::from), url.map(String::from))
+    }
+
+    fn api(profile: ApiProfile, base_url: &str, from_env: bool) -> Option<ApiSettings> {
+        Some(ApiSettings {
+            profile,
+            base_url: base_url.to_string(),
+            from_env,
+        })
+    }
+
+    #[test]
+    fn settings_file_without_env() {
+        let no_env = ApiEnv::default();
+        let cases = [
+            (
+                settings(ApiProfile::Prod, None),
+                api(ApiProfile::Prod, "https://echo.i.inc", false),
+            ),
+            (
+                settings(ApiProfile::Local, None),
+                api(ApiProfile::Local, "http://localhost:8000", false),
+            ),
+            (
+                settings(ApiProfile::Prod, Some("http://10.0.0.2:8000/")),
+                api(ApiProfile::Prod, "http://10.0.0.2:8000", false),
+            ),
+            (
+                settings(ApiProfile::Staging, Some(CUSTOM_URL)),
+                api(ApiProfile::Staging, CUSTOM_URL, false),
+            ),
+            (settings(ApiProfile::Staging, None), None),
+        ];
+        for (settings, expected) in cases {
+            assert_eq!(resolve_api(&settings, &no_env), expected, "{:?}", settings);
+        }
+    }
+
+    #[test]
+    fn env_profile_beats_settings_file() {
+        let local_env = env(Some("local"), None);
+        for settings in [
+            settings(ApiProfile::Prod, None),
+            settings(ApiProfile::Staging, Some(CUSTOM_URL)),
+        ] {
+            assert_eq!(
+                resolve_api(&settings, &local_env),
+                api(ApiProfile::Local, "http://localhost:8000", true)
+            );
+        }
+    }
+
+    #[test]
+    fn env_url_beats_every_profile() {
+        let settings = settings(ApiProfile::Local, Some("http://10.0.0.2:8000"));
+        assert_eq!(
+            resolve_api(&settings, &env(None, Some("http://mock:9000/"))),
+            api(ApiProfile::Local, "http://mock:9000", true)
+        );
+        assert_eq!(
+            resolve_api(&settings, &env(Some("prod"), Some("http://mock:9000"))),
+            api(ApiProfile::Prod, "http://mock:9000", true)
+        );
+        assert_eq!(
+            resolve_api(&settings, &env(Some("staging"), Some(CUSTOM_URL))),
+            api(ApiProfile::Staging, CUSTOM_URL, true)
+        );
+    }
+
+    #[test]
+    fn env_staging_without_url_is_ignored() {
+        let staging_env = env(Some("staging"), None);
+        assert_eq!(staging_env, ApiEnv::default());
+        assert_eq!(
+            resolve_api(&settings(ApiProfile::Local, None), &staging_env),
+            api(ApiProfile::Local, "http://localhost:8000", false)
+        );
+        // The staging URL of the settings file still applies
+        assert_eq!(
+            resolve_api(
+                &settings(ApiProfile::Staging, Some(CUSTOM_URL)),
+                &staging_env
+            ),
+            api(ApiProfile::Staging, CUSTOM_URL, false)
+        );
+        assert_eq!(env(Some("staging"), Some("")), ApiEnv::default());
+    }
+
+    #[test]
+    fn env_parsing() {
+        assert_eq!(env(Some("LOCAL"), None).profile, Some(ApiProfile::Local));
+        assert_eq!(env(Some("qa"), None), ApiEnv::default());
+        assert_eq!(env(None, Some("")), ApiEnv::default());
+        assert_eq!(env(None, Some(CUSTOM_URL)).url.as_deref(), Some(CUSTOM_URL));
+    }
+}
@@ -1,4 +1,4 @@
-use rdev::{Button, Key};
+use rdev::{Button, Key, RawKey};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -22,9 +22,9 @@ impl fmt::Display for MouseAction {
     }
 }
 
-impl Into<MouseAction> for Button {
-    fn into(self) -> MouseAction {
-        match self {
+impl From<Button> for MouseAction {
+    fn from(button: Button) -> Self {
+        match button {
             Button::Left => MouseAction::Left,
             Button::Right => MouseAction::Right,
             Button::Middle => MouseAction::Middle,
@@ -35,6 +35,43 @@ impl Into<MouseAction> for Button {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")] // JSON value name
+pub enum ButtonState {
+    Pressed,
+    Released,
+}
+
+/// A cursor position sampled along a mouse gesture
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub struct PathSample {
+    pub x: i32,
+    pub y: i32,
+    pub timestamp_nanos: i64,
+}
+
+/// A stretch of cursor motion between other input events, sampled per `TrajectorySampling`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub struct MouseMoveAction {
+    pub path: Vec<PathSample>,
+}
+
+/// A press, move and release of the same mouse button, e.g. drag-and-drop, text selection or
+/// window resizing
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub struct DragAction {
+    pub button: MouseAction,
+    pub start_x: i32,
+    pub start_y: i32,
+    pub end_x: i32,
+    pub end_y: i32,
+    pub path: Vec<PathSample>,
+    pub duration: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
 pub enum KeyboardActionKey {
     // Modifier Keys
     #[serde(rename = "caps_lock")]
@@ -57,6 +94,18 @@ pub enum KeyboardActionKey {
     F10,
     F11,
     F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
     // Alphabet Keys
     A,
     B,
@@ -105,6 +154,43 @@ pub enum KeyboardActionKey {
     Num8,
     #[serde(rename = "9")]
     Num9,
+    // Keypad Keys
+    #[serde(rename = "kp_0")]
+    Kp0,
+    #[serde(rename = "kp_1")]
+    Kp1,
+    #[serde(rename = "kp_2")]
+    Kp2,
+    #[serde(rename = "kp_3")]
+    Kp3,
+    #[serde(rename = "kp_4")]
+    Kp4,
+    #[serde(rename = "kp_5")]
+    Kp5,
+    #[serde(rename = "kp_6")]
+    Kp6,
+    #[serde(rename = "kp_7")]
+    Kp7,
+    #[serde(rename = "kp_8")]
+    Kp8,
+    #[serde(rename = "kp_9")]
+    Kp9,
+    #[serde(rename = "kp_return")]
+    KpReturn,
+    #[serde(rename = "kp_minus")]
+    KpMinus,
+    #[serde(rename = "kp_plus")]
+    KpPlus,
+    #[serde(rename = "kp_multiply")]
+    KpMultiply,
+    #[serde(rename = "kp_divide")]
+    KpDivide,
+    #[serde(rename = "kp_decimal")]
+    KpDecimal,
+    #[serde(rename = "kp_equal")]
+    KpEqual,
+    #[serde(rename = "kp_comma")]
+    KpComma,
     // Navigation Keys
     #[serde(rename = "arrow_up")]
     ArrowUp,
@@ -135,6 +221,22 @@ pub enum KeyboardActionKey {
     Pause,
     #[serde(rename = "print_screen")]
     PrintScreen,
+    Apps,
+    Cancel,
+    Clear,
+    Print,
+    Select,
+    Execute,
+    Help,
+    Sleep,
+    Separator,
+    // Media Keys
+    #[serde(rename = "volume_up")]
+    VolumeUp,
+    #[serde(rename = "volume_down")]
+    VolumeDown,
+    #[serde(rename = "volume_mute")]
+    VolumeMute,
     // Symbols
     Grave,
     Minus,
@@ -149,13 +251,65 @@ pub enum KeyboardActionKey {
     Period,
     Slash,
     Backslash,
+    // International and IME Keys
+    #[serde(rename = "intl_backslash")]
+    IntlBackslash,
+    #[serde(rename = "intl_ro")]
+    IntlRo,
+    #[serde(rename = "intl_yen")]
+    IntlYen,
+    #[serde(rename = "kana_mode")]
+    KanaMode,
+    Lang1,
+    Lang2,
+    Lang3,
+    Lang4,
+    Lang5,
+    Kana,
+    Hangul,
+    Junja,
+    Final,
+    Hanja,
+    // Platform key code rdev couldn't name
     Unknown(u32),
-    // RawKey(RawKey),
+    // Key rdev only has the platform's code for, e.g. an X keycode
+    Raw(RawKeyCode),
 }
 
-impl Into<KeyboardActionKey> for rdev::Key {
-    fn into(self) -> KeyboardActionKey {
-        match self {
+/// A key code as the platform reported it, only comparable within the same `platform`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RawKeyCode {
+    pub platform: RawKeyPlatform,
+    pub code: u32,
+}
+
+/// The kind of code in a `RawKeyCode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")] // JSON value name
+pub enum RawKeyPlatform {
+    ScanCode,
+    WinVirtualKeycode,
+    LinuxXorgKeycode,
+    LinuxConsoleKeycode,
+    MacVirtualKeycode,
+}
+
+impl From<RawKey> for RawKeyCode {
+    fn from(raw_key: RawKey) -> Self {
+        let (platform, code) = match raw_key {
+            RawKey::ScanCode(code) => (RawKeyPlatform::ScanCode, code),
+            RawKey::WinVirtualKeycode(code) => (RawKeyPlatform::WinVirtualKeycode, code),
+            RawKey::LinuxXorgKeycode(code) => (RawKeyPlatform::LinuxXorgKeycode, code),
+            RawKey::LinuxConsoleKeycode(code) => (RawKeyPlatform::LinuxConsoleKeycode, code),
+            RawKey::MacVirtualKeycode(code) => (RawKeyPlatform::MacVirtualKeycode, code),
+        };
+        RawKeyCode { platform, code }
+    }
+}
+
+impl From<rdev::Key> for KeyboardActionKey {
+    fn from(key: rdev::Key) -> Self {
+        match key {
             Key::Alt => KeyboardActionKey::Alt,
             Key::AltGr => KeyboardActionKey::Alt,
             Key::Backspace => KeyboardActionKey::Backspace,
@@ -170,18 +324,18 @@ impl Into<KeyboardActionKey> for rdev::Key {
             Key::F10 => KeyboardActionKey::F10,
             Key::F11 => KeyboardActionKey::F11,
             Key::F12 => KeyboardActionKey::F12,
-            // Key::F13 => KeyboardActionKey::F13,
-            // Key::F14 => KeyboardActionKey::F14,
-            // Key::F15 => KeyboardActionKey::F15,
-            // Key::F16 => KeyboardActionKey::F16,
-            // Key::F17 => KeyboardActionKey::F17,
-            // Key::F18 => KeyboardActionKey::F18,
-            // Key::F19 => KeyboardActionKey::F19,
-            // Key::F20 => KeyboardActionKey::F20,
-            // Key::F21 => KeyboardActionKey::F21,
-            // Key::F22 => KeyboardActionKey::F22,
-            // Key::F23 => KeyboardActionKey::F23,
-            // Key::F24 => KeyboardActionKey::F24,
+            Key::F13 => KeyboardActionKey::F13,
+            Key::F14 => KeyboardActionKey::F14,
+            Key::F15 => KeyboardActionKey::F15,
+            Key::F16 => KeyboardActionKey::F16,
+            Key::F17 => KeyboardActionKey::F17,
+            Key::F18 => KeyboardActionKey::F18,
+            Key::F19 => KeyboardActionKey::F19,
+            Key::F20 => KeyboardActionKey::F20,
+            Key::F21 => KeyboardActionKey::F21,
+            Key::F22 => KeyboardActionKey::F22,
+            Key::F23 => KeyboardActionKey::F23,
+            Key::F24 => KeyboardActionKey::F24,
             Key::F2 => KeyboardActionKey::F2,
             Key::F3 => KeyboardActionKey::F3,
             Key::F4 => KeyboardActionKey::F4,
@@ -244,10 +398,10 @@ impl Into<KeyboardActionKey> for rdev::Key {
             Key::SemiColon => KeyboardActionKey::Semicolon,
             Key::Quote => KeyboardActionKey::Quote,
             Key::BackSlash => KeyboardActionKey::Backslash,
-            // Key::IntlBackslash => KeyboardActionKey::IntlBackslash,
-            // Key::IntlRo => KeyboardActionKey::IntlRo,   // Brazilian /? and Japanese _ 'ro'
-            // Key::IntlYen => KeyboardActionKey::IntlYen,  // Japanese Henkan (Convert) key.
-            // Key::KanaMode => KeyboardActionKey::KanaMode, // Japanese Hiragana/Katakana key.
+            Key::IntlBackslash => KeyboardActionKey::IntlBackslash,
+            Key::IntlRo => KeyboardActionKey::IntlRo, // Brazilian /? and Japanese _ 'ro'
+            Key::IntlYen => KeyboardActionKey::IntlYen, // Japanese Henkan (Convert) key.
+            Key::KanaMode => KeyboardActionKey::KanaMode, // Japanese Hiragana/Katakana key.
             Key::KeyZ => KeyboardActionKey::Z,
             Key::KeyX => KeyboardActionKey::X,
             Key::KeyC => KeyboardActionKey::C,
@@ -259,59 +413,214 @@ impl Into<KeyboardActionKey> for rdev::Key {
             Key::Dot => KeyboardActionKey::Period,
             Key::Slash => KeyboardActionKey::Slash,
             Key::Insert => KeyboardActionKey::Insert,
-            // Key::KpReturn => KeyboardActionKey::KpReturn,
-            // Key::KpMinus => KeyboardActionKey::KpMinus,
-            // Key::KpPlus => KeyboardActionKey::KpPlus,
-            // Key::KpMultiply => KeyboardActionKey::KpMultiply,
-            // Key::KpDivide => KeyboardActionKey::KpDivide,
-            // Key::KpDecimal => KeyboardActionKey::KpDecimal,
-            // Key::KpEqual => KeyboardActionKey::KpEqual,
-            // Key::KpComma => KeyboardActionKey::KpComma,
-            // Key::Kp0 => KeyboardActionKey::Kp0,
-            // Key::Kp1 => KeyboardActionKey::Kp1,
-            // Key::Kp2 => KeyboardActionKey::Kp2,
-            // Key::Kp3 => KeyboardActionKey::Kp3,
-            // Key::Kp4 => KeyboardActionKey::Kp4,
-            // Key::Kp5 => KeyboardActionKey::Kp5,
-            // Key::Kp6 => KeyboardActionKey::Kp6,
-            // Key::Kp7 => KeyboardActionKey::Kp7,
-            // Key::Kp8 => KeyboardActionKey::Kp8,
-            // Key::Kp9 => KeyboardActionKey::Kp9,
-            // Key::VolumeUp => KeyboardActionKey::VolumeUp,
-            // Key::VolumeDown => KeyboardActionKey::VolumeDown,
-            // Key::VolumeMute => KeyboardActionKey::VolumeMute,
-            // Key::Lang1 => KeyboardActionKey::Lang1, // Korean Hangul/English toggle key, and as the Kana key on the Apple Japanese keyboard.
-            // Key::Lang2 => KeyboardActionKey::Lang2, // Korean Hanja conversion key, and as the Eisu key on the Apple Japanese keyboard.
-            // Key::Lang3 => KeyboardActionKey::Lang3, // Japanese Katakana key.
-            // Key::Lang4 => KeyboardActionKey::Lang4, // Japanese Hiragana key.
-            // Key::Lang5 => KeyboardActionKey::Lang5, // Japanese Zenkaku/Hankaku (Fullwidth/halfwidth) key.
+            Key::KpReturn => KeyboardActionKey::KpReturn,
+            Key::KpMinus => KeyboardActionKey::KpMinus,
+            Key::KpPlus => KeyboardActionKey::KpPlus,
+            Key::KpMultiply => KeyboardActionKey::KpMultiply,
+            Key::KpDivide => KeyboardActionKey::KpDivide,
+            Key::KpDecimal => KeyboardActionKey::KpDecimal,
+            Key::KpEqual => KeyboardActionKey::KpEqual,
+            Key::KpComma => KeyboardActionKey::KpComma,
+            Key::Kp0 => KeyboardActionKey::Kp0,
+            Key::Kp1 => KeyboardActionKey::Kp1,
+            Key::Kp2 => KeyboardActionKey::Kp2,
+            Key::Kp3 => KeyboardActionKey::Kp3,
+            Key::Kp4 => KeyboardActionKey::Kp4,
+            Key::Kp5 => KeyboardActionKey::Kp5,
+            Key::Kp6 => KeyboardActionKey::Kp6,
+            Key::Kp7 => KeyboardActionKey::Kp7,
+            Key::Kp8 => KeyboardActionKey::Kp8,
+            Key::Kp9 => KeyboardActionKey::Kp9,
+            Key::VolumeUp => KeyboardActionKey::VolumeUp,
+            Key::VolumeDown => KeyboardActionKey::VolumeDown,
+            Key::VolumeMute => KeyboardActionKey::VolumeMute,
+            Key::Lang1 => KeyboardActionKey::Lang1, // Korean Hangul/English toggle key, and as the Kana key on the Apple Japanese keyboard.
+            Key::Lang2 => KeyboardActionKey::Lang2, // Korean Hanja conversion key, and as the Eisu key on the Apple Japanese keyboard.
+            Key::Lang3 => KeyboardActionKey::Lang3, // Japanese Katakana key.
+            Key::Lang4 => KeyboardActionKey::Lang4, // Japanese Hiragana key.
+            Key::Lang5 => KeyboardActionKey::Lang5, // Japanese Zenkaku/Hankaku (Fullwidth/halfwidth) key.
             Key::Function => KeyboardActionKey::Fn,
-            // Key::Apps => KeyboardActionKey::Apps,
-            // Key::Cancel => KeyboardActionKey::Cancel,
-            // Key::Clear => KeyboardActionKey::Clear,
-            // Key::Kana => KeyboardActionKey::Kana,
-            // Key::Hangul => KeyboardActionKey::Hangul,
-            // Key::Junja => KeyboardActionKey::Junja,
-            // Key::Final => KeyboardActionKey::Final,
-            // Key::Hanja => KeyboardActionKey::Hanja,
-            // Key::Print => KeyboardActionKey::Print,
-            // Key::Select => KeyboardActionKey::Select,
-            // Key::Execute => KeyboardActionKey::Execute,
-            // Key::Help => KeyboardActionKey::Help,
-            // Key::Sleep => KeyboardActionKey::Sleep,
-            // Key::Separator => KeyboardActionKey::Separator,
-            Key::Unknown(u32) => KeyboardActionKey::Unknown(u32),
-            // Key::RawKey(RawKey) => KeyboardActionKey::RawKey(RawKey),
-            _ => KeyboardActionKey::Unknown(0),
+            Key::Apps => KeyboardActionKey::Apps,
+            Key::Cancel => KeyboardActionKey::Cancel,
+            Key::Clear => KeyboardActionKey::Clear,
+            Key::Kana => KeyboardActionKey::Kana,
+            Key::Hangul => KeyboardActionKey::Hangul,
+            Key::Junja => KeyboardActionKey::Junja,
+            Key::Final => KeyboardActionKey::Final,
+            // One virtual key on Windows, VK_HANJA and VK_KANJI are both 0x19
+            Key::Hanja | Key::Hanji => KeyboardActionKey::Hanja,
+            Key::Print => KeyboardActionKey::Print,
+            Key::Select => KeyboardActionKey::Select,
+            Key::Execute => KeyboardActionKey::Execute,
+            Key::Help => KeyboardActionKey::Help,
+            Key::Sleep => KeyboardActionKey::Sleep,
+            Key::Separator => KeyboardActionKey::Separator,
+            Key::Unknown(code) => KeyboardActionKey::Unknown(code),
+            Key::RawKey(raw_key) => KeyboardActionKey::Raw(raw_key.into()),
         }
     }
 }
 
+/// The focused window when an input event happened. Bounds are in screen pixels.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub struct WindowContext {
+    /// Platform window handle, only meaningful for telling windows apart within a session
+    pub window_id: u64,
+    pub app_name: Option<String>,
+    pub window_title: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Keyboard focus moved to another window. The new window is the event's `window`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub struct FocusAction {
+    pub previous: Option<WindowContext>,
+}
+
+/// Marks a stretch of the session with something missing. Events and frames between `paused`
+/// and the following `resumed` were deliberately not recorded. Between `gap` and the following
+/// `resumed` the video capture had failed, input events were still recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum RecordingAction {
+    Paused,
+    Resumed,
+    Gap,
+}
+
+/// Which of a pair of keys was used. `KeyboardActionKey` keeps the coarse name (e.g. `shift`) so
+/// existing consumers are unaffected, the side is carried next to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum KeySide {
+    Left,
+    Right,
+}
+
+impl KeySide {
+    /// The side of a modifier key, `None` for keys that only exist once. AltGr is the right Alt
+    /// key (right Option on macOS).
+    pub fn of(key: Key) -> Option<KeySide> {
+        match key {
+            Key::ShiftLeft | Key::ControlLeft | Key::MetaLeft | Key::Alt => Some(KeySide::Left),
+            Key::ShiftRight | Key::ControlRight | Key::MetaRight | Key::AltGr => {
+                Some(KeySide::Right)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Bitset of the modifier keys held down when an input event happened, left and right keys have
+/// their own bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Modifiers(u16);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT_LEFT: Modifiers = Modifiers(1 << 0);
+    pub const SHIFT_RIGHT: Modifiers = Modifiers(1 << 1);
+    pub const CONTROL_LEFT: Modifiers = Modifiers(1 << 2);
+    pub const CONTROL_RIGHT: Modifiers = Modifiers(1 << 3);
+    pub const ALT: Modifiers = Modifiers(1 << 4);
+    pub const ALT_GR: Modifiers = Modifiers(1 << 5);
+    pub const META_LEFT: Modifiers = Modifiers(1 << 6);
+    pub const META_RIGHT: Modifiers = Modifiers(1 << 7);
+    pub const FN: Modifiers = Modifiers(1 << 8);
+
+    /// The modifier bit for a key, `None` if the key is not a modifier
+    pub fn from_key(key: Key) -> Option<Modifiers> {
+        match key {
+            Key::ShiftLeft => Some(Modifiers::SHIFT_LEFT),
+            Key::ShiftRight => Some(Modifiers::SHIFT_RIGHT),
+            Key::ControlLeft => Some(Modifiers::CONTROL_LEFT),
+            Key::ControlRight => Some(Modifiers::CONTROL_RIGHT),
+            Key::Alt => Some(Modifiers::ALT),
+            Key::AltGr => Some(Modifiers::ALT_GR),
+            Key::MetaLeft => Some(Modifiers::META_LEFT),
+            Key::MetaRight => Some(Modifiers::META_RIGHT),
+            Key::Function => Some(Modifiers::FN),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn intersects(self, other: Modifiers) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: Modifiers) {
+        self.0 |= other.0;
+    }
+
+    /// Held modifiers as chord keys in canonical order, both sides of a modifier collapse into
+    /// one key
+    pub fn chord_keys(self) -> Vec<KeyboardActionKey> {
+        let mut keys = Vec::new();
+        if self.intersects(Modifiers(
+            Modifiers::CONTROL_LEFT.0 | Modifiers::CONTROL_RIGHT.0,
+        )) {
+            keys.push(KeyboardActionKey::Control);
+        }
+        if self.intersects(Modifiers(Modifiers::ALT.0 | Modifiers::ALT_GR.0)) {
+            keys.push(KeyboardActionKey::Alt);
+        }
+        if self.intersects(Modifiers(
+            Modifiers::SHIFT_LEFT.0 | Modifiers::SHIFT_RIGHT.0,
+        )) {
+            keys.push(KeyboardActionKey::Shift);
+        }
+        if self.intersects(Modifiers(Modifiers::META_LEFT.0 | Modifiers::META_RIGHT.0)) {
+            keys.push(KeyboardActionKey::Meta);
+        }
+        if self.intersects(Modifiers::FN) {
+            keys.push(KeyboardActionKey::Fn);
+        }
+        keys
+    }
+}
+
+/// A non-modifier key pressed while modifiers were held, e.g. Cmd+Shift+T is
+/// `{"modifiers": ["meta", "shift"], "key": "t"}`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")] // JSON value name
+pub struct KeyChord {
+    pub modifiers: Vec<KeyboardActionKey>,
+    pub key: KeyboardActionKey,
+}
+
+impl KeyChord {
+    /// `None` when no modifiers are held or the key itself is a modifier
+    pub fn new(modifiers: Modifiers, key: Key) -> Option<KeyChord> {
+        if modifiers.is_empty() || Modifiers::from_key(key).is_some() {
+            return None;
+        }
+        Some(KeyChord {
+            modifiers: modifiers.chord_keys(),
+            key: key.into(),
+        })
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")] // JSON value name
 pub struct KeyboardAction {
     pub key: KeyboardActionKey,
+    pub side: Option<KeySide>,
     pub duration: i32,
+    pub chord: Option<KeyChord>,
+    /// Text produced by the key with the active layout, including auto-repeats while it was held.
+    /// `None` for keys that don't produce text, like modifiers or dead keys.
+    pub text: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -320,3 +629,415 @@ pub struct ScrollAction {
     pub x: i32,
     pub y: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(key: KeyboardActionKey, json: &str) {
+        assert_eq!(
+            serde_json::to_string(&key).unwrap(),
+            json,
+            "serializing {:?}",
+            key
+        );
+        assert_eq!(
+            serde_json::from_str::<KeyboardActionKey>(json).unwrap(),
+            key,
+            "deserializing {}",
+            json
+        );
+    }
+
+    #[test]
+    fn keyboard_action_key_wire_names() {
+        let keys = [
+            (KeyboardActionKey::CapsLock, "\"caps_lock\""),
+            (KeyboardActionKey::Shift, "\"shift\""),
+            (KeyboardActionKey::Control, "\"control\""),
+            (KeyboardActionKey::Fn, "\"fn\""),
+            (KeyboardActionKey::Alt, "\"alt\""),
+            (KeyboardActionKey::Meta, "\"meta\""),
+            (KeyboardActionKey::F1, "\"f1\""),
+            (KeyboardActionKey::F2, "\"f2\""),
+            (KeyboardActionKey::F3, "\"f3\""),
+            (KeyboardActionKey::F4, "\"f4\""),
+            (KeyboardActionKey::F5, "\"f5\""),
+            (KeyboardActionKey::F6, "\"f6\""),
+            (KeyboardActionKey::F7, "\"f7\""),
+            (KeyboardActionKey::F8, "\"f8\""),
+            (KeyboardActionKey::F9, "\"f9\""),
+            (KeyboardActionKey::F10, "\"f10\""),
+            (KeyboardActionKey::F11, "\"f11\""),
+            (KeyboardActionKey::F12, "\"f12\""),
+            (KeyboardActionKey::F13, "\"f13\""),
+            (KeyboardActionKey::F14, "\"f14\""),
+            (KeyboardActionKey::F15, "\"f15\""),
+            (KeyboardActionKey::F16, "\"f16\""),
+            (KeyboardActionKey::F17, "\"f17\""),
+            (KeyboardActionKey::F18, "\"f18\""),
+            (KeyboardActionKey::F19, "\"f19\""),
+            (KeyboardActionKey::F20, "\"f20\""),
+            (KeyboardActionKey::F21, "\"f21\""),
+            (KeyboardActionKey::F22, "\"f22\""),
+            (KeyboardActionKey::F23, "\"f23\""),
+            (KeyboardActionKey::F24, "\"f24\""),
+            (KeyboardActionKey::A, "\"a\""),
+            (KeyboardActionKey::B, "\"b\""),
+            (KeyboardActionKey::C, "\"c\""),
+            (KeyboardActionKey::D, "\"d\""),
+            (KeyboardActionKey::E, "\"e\""),
+            (KeyboardActionKey::F, "\"f\""),
+            (KeyboardActionKey::G, "\"g\""),
+            (KeyboardActionKey::H, "\"h\""),
+            (KeyboardActionKey::I, "\"i\""),
+            (KeyboardActionKey::J, "\"j\""),
+            (KeyboardActionKey::K, "\"k\""),
+            (KeyboardActionKey::L, "\"l\""),
+            (KeyboardActionKey::M, "\"m\""),
+            (KeyboardActionKey::N, "\"n\""),
+            (KeyboardActionKey::O, "\"o\""),
+            (KeyboardActionKey::P, "\"p\""),
+            (KeyboardActionKey::Q, "\"q\""),
+            (KeyboardActionKey::R, "\"r\""),
+            (KeyboardActionKey::S, "\"s\""),
+            (KeyboardActionKey::T, "\"t\""),
+            (KeyboardActionKey::U, "\"u\""),
+            (KeyboardActionKey::V, "\"v\""),
+            (KeyboardActionKey::W, "\"w\""),
+            (KeyboardActionKey::X, "\"x\""),
+            (KeyboardActionKey::Y, "\"y\""),
+            (KeyboardActionKey::Z, "\"z\""),
+            (KeyboardActionKey::Num0, "\"0\""),
+            (KeyboardActionKey::Num1, "\"1\""),
+            (KeyboardActionKey::Num2, "\"2\""),
+            (KeyboardActionKey::Num3, "\"3\""),
+            (KeyboardActionKey::Num4, "\"4\""),
+            (KeyboardActionKey::Num5, "\"5\""),
+            (KeyboardActionKey::Num6, "\"6\""),
+            (KeyboardActionKey::Num7, "\"7\""),
+            (KeyboardActionKey::Num8, "\"8\""),
+            (KeyboardActionKey::Num9, "\"9\""),
+            (KeyboardActionKey::Kp0, "\"kp_0\""),
+            (KeyboardActionKey::Kp1, "\"kp_1\""),
+            (KeyboardActionKey::Kp2, "\"kp_2\""),
+            (KeyboardActionKey::Kp3, "\"kp_3\""),
+            (KeyboardActionKey::Kp4, "\"kp_4\""),
+            (KeyboardActionKey::Kp5, "\"kp_5\""),
+            (KeyboardActionKey::Kp6, "\"kp_6\""),
+            (KeyboardActionKey::Kp7, "\"kp_7\""),
+            (KeyboardActionKey::Kp8, "\"kp_8\""),
+            (KeyboardActionKey::Kp9, "\"kp_9\""),
+            (KeyboardActionKey::KpReturn, "\"kp_return\""),
+            (KeyboardActionKey::KpMinus, "\"kp_minus\""),
+            (KeyboardActionKey::KpPlus, "\"kp_plus\""),
+            (KeyboardActionKey::KpMultiply, "\"kp_multiply\""),
+            (KeyboardActionKey::KpDivide, "\"kp_divide\""),
+            (KeyboardActionKey::KpDecimal, "\"kp_decimal\""),
+            (KeyboardActionKey::KpEqual, "\"kp_equal\""),
+            (KeyboardActionKey::KpComma, "\"kp_comma\""),
+            (KeyboardActionKey::ArrowUp, "\"arrow_up\""),
+            (KeyboardActionKey::ArrowDown, "\"arrow_down\""),
+            (KeyboardActionKey::ArrowLeft, "\"arrow_left\""),
+            (KeyboardActionKey::ArrowRight, "\"arrow_right\""),
+            (KeyboardActionKey::Home, "\"home\""),
+            (KeyboardActionKey::End, "\"end\""),
+            (KeyboardActionKey::PageUp, "\"page_up\""),
+            (KeyboardActionKey::PageDown, "\"page_down\""),
+            (KeyboardActionKey::Escape, "\"escape\""),
+            (KeyboardActionKey::Enter, "\"enter\""),
+            (KeyboardActionKey::Tab, "\"tab\""),
+            (KeyboardActionKey::Space, "\"space\""),
+            (KeyboardActionKey::Backspace, "\"backspace\""),
+            (KeyboardActionKey::Insert, "\"insert\""),
+            (KeyboardActionKey::Delete, "\"delete\""),
+            (KeyboardActionKey::NumLock, "\"num_lock\""),
+            (KeyboardActionKey::ScrollLock, "\"scroll_lock\""),
+            (KeyboardActionKey::Pause, "\"pause\""),
+            (KeyboardActionKey::PrintScreen, "\"print_screen\""),
+            (KeyboardActionKey::Apps, "\"apps\""),
+            (KeyboardActionKey::Cancel, "\"cancel\""),
+            (KeyboardActionKey::Clear, "\"clear\""),
+            (KeyboardActionKey::Print, "\"print\""),
+            (KeyboardActionKey::Select, "\"select\""),
+            (KeyboardActionKey::Execute, "\"execute\""),
+            (KeyboardActionKey::Help, "\"help\""),
+            (KeyboardActionKey::Sleep, "\"sleep\""),
+            (KeyboardActionKey::Separator, "\"separator\""),
+            (KeyboardActionKey::VolumeUp, "\"volume_up\""),
+            (KeyboardActionKey::VolumeDown, "\"volume_down\""),
+            (KeyboardActionKey::VolumeMute, "\"volume_mute\""),
+            (KeyboardActionKey::Grave, "\"grave\""),
+            (KeyboardActionKey::Minus, "\"minus\""),
+            (KeyboardActionKey::Equal, "\"equal\""),
+            (KeyboardActionKey::BracketLeft, "\"bracket_left\""),
+            (KeyboardActionKey::BracketRight, "\"bracket_right\""),
+            (KeyboardActionKey::Semicolon, "\"semicolon\""),
+            (KeyboardActionKey::Quote, "\"quote\""),
+            (KeyboardActionKey::Comma, "\"comma\""),
+            (KeyboardActionKey::Period, "\"period\""),
+            (KeyboardActionKey::Slash, "\"slash\""),
+            (KeyboardActionKey::Backslash, "\"backslash\""),
+            (KeyboardActionKey::IntlBackslash, "\"intl_backslash\""),
+            (KeyboardActionKey::IntlRo, "\"intl_ro\""),
+            (KeyboardActionKey::IntlYen, "\"intl_yen\""),
+            (KeyboardActionKey::KanaMode, "\"kana_mode\""),
+            (KeyboardActionKey::Lang1, "\"lang1\""),
+            (KeyboardActionKey::Lang2, "\"lang2\""),
+            (KeyboardActionKey::Lang3, "\"lang3\""),
+            (KeyboardActionKey::Lang4, "\"lang4\""),
+            (KeyboardActionKey::Lang5, "\"lang5\""),
+            (KeyboardActionKey::Kana, "\"kana\""),
+            (KeyboardActionKey::Hangul, "\"hangul\""),
+            (KeyboardActionKey::Junja, "\"junja\""),
+            (KeyboardActionKey::Final, "\"final\""),
+            (KeyboardActionKey::Hanja, "\"hanja\""),
+        ];
+        for (key, json) in keys {
+            assert_round_trip(key, json);
+        }
+    }
+
+    #[test]
+    fn keyboard_action_key_raw_codes() {
+        assert_round_trip(KeyboardActionKey::Unknown(42), r#"{"unknown":42}"#);
+        for (raw_key, json) in [
+            (
+                RawKey::ScanCode(57),
+                r#"{"raw":{"platform":"scan_code","code":57}}"#,
+            ),
+            (
+                RawKey::WinVirtualKeycode(0x5d),
+                r#"{"raw":{"platform":"win_virtual_keycode","code":93}}"#,
+            ),
+            (
+                RawKey::LinuxXorgKeycode(135),
+                r#"{"raw":{"platform":"linux_xorg_keycode","code":135}}"#,
+            ),
+            (
+                RawKey::LinuxConsoleKeycode(127),
+                r#"{"raw":{"platform":"linux_console_keycode","code":127}}"#,
+            ),
+            (
+                RawKey::MacVirtualKeycode(110),
+                r#"{"raw":{"platform":"mac_virtual_keycode","code":110}}"#,
+            ),
+        ] {
+            assert_round_trip(KeyboardActionKey::from(Key::RawKey(raw_key)), json);
+        }
+    }
+
+    #[test]
+    fn sided_keys_keep_coarse_names() {
+        for (key, name, side) in [
+            (Key::ShiftLeft, "\"shift\"", KeySide::Left),
+            (Key::ShiftRight, "\"shift\"", KeySide::Right),
+            (Key::ControlLeft, "\"control\"", KeySide::Left),
+            (Key::ControlRight, "\"control\"", KeySide::Right),
+            (Key::MetaLeft, "\"meta\"", KeySide::Left),
+            (Key::MetaRight, "\"meta\"", KeySide::Right),
+            (Key::Alt, "\"alt\"", KeySide::Left),
+            (Key::AltGr, "\"alt\"", KeySide::Right),
+        ] {
+            assert_eq!(
+                serde_json::to_string(&KeyboardActionKey::from(key)).unwrap(),
+                name
+            );
+            assert_eq!(KeySide::of(key), Some(side));
+        }
+        assert_eq!(KeySide::of(Key::KeyA), None);
+    }
+
+    /// Every unit `rdev::Key`. The match fails to compile when rdev adds a key that isn't listed.
+    macro_rules! every_key {
+        ($($key:ident),* $(,)?) => {{
+            fn listed(key: Key) {
+                match key {
+                    $(Key::$key)|* | Key::Unknown(_) | Key::RawKey(_) => {}
+                }
+            }
+            let keys = vec![$(Key::$key),*];
+            keys.iter().copied().for_each(listed);
+            keys
+        }};
+    }
+
+    #[test]
+    fn every_key_has_its_own_wire_name() {
+        let keys = every_key![
+            Alt,
+            AltGr,
+            Backspace,
+            CapsLock,
+            ControlLeft,
+            ControlRight,
+            Delete,
+            DownArrow,
+            End,
+            Escape,
+            F1,
+            F10,
+            F11,
+            F12,
+            F13,
+            F14,
+            F15,
+            F16,
+            F17,
+            F18,
+            F19,
+            F20,
+            F21,
+            F22,
+            F23,
+            F24,
+            F2,
+            F3,
+            F4,
+            F5,
+            F6,
+            F7,
+            F8,
+            F9,
+            Home,
+            LeftArrow,
+            MetaLeft,
+            MetaRight,
+            PageDown,
+            PageUp,
+            Return,
+            RightArrow,
+            ShiftLeft,
+            ShiftRight,
+            Space,
+            Tab,
+            UpArrow,
+            PrintScreen,
+            ScrollLock,
+            Pause,
+            NumLock,
+            BackQuote,
+            Num1,
+            Num2,
+            Num3,
+            Num4,
+            Num5,
+            Num6,
+            Num7,
+            Num8,
+            Num9,
+            Num0,
+            Minus,
+            Equal,
+            KeyQ,
+            KeyW,
+            KeyE,
+            KeyR,
+            KeyT,
+            KeyY,
+            KeyU,
+            KeyI,
+            KeyO,
+            KeyP,
+            LeftBracket,
+            RightBracket,
+            KeyA,
+            KeyS,
+            KeyD,
+            KeyF,
+            KeyG,
+            KeyH,
+            KeyJ,
+            KeyK,
+            KeyL,
+            SemiColon,
+            Quote,
+            BackSlash,
+            IntlBackslash,
+            IntlRo,
+            IntlYen,
+            KanaMode,
+            KeyZ,
+            KeyX,
+            KeyC,
+            KeyV,
+            KeyB,
+            KeyN,
+            KeyM,
+            Comma,
+            Dot,
+            Slash,
+            Insert,
+            KpReturn,
+            KpMinus,
+            KpPlus,
+            KpMultiply,
+            KpDivide,
+            KpDecimal,
+            KpEqual,
+            KpComma,
+            Kp0,
+            Kp1,
+            Kp2,
+            Kp3,
+            Kp4,
+            Kp5,
+            Kp6,
+            Kp7,
+            Kp8,
+            Kp9,
+            VolumeUp,
+            VolumeDown,
+            VolumeMute,
+            Lang1,
+            Lang2,
+            Lang3,
+            Lang4,
+            Lang5,
+            Function,
+            Apps,
+            Cancel,
+            Clear,
+            Kana,
+            Hangul,
+            Junja,
+            Final,
+            Hanja,
+            Hanji,
+            Print,
+            Select,
+            Execute,
+            Help,
+            Sleep,
+            Separator,
+        ];
+
+        let mut names: std::collections::HashMap<String, Key> = Default::default();
+        for key in keys {
+            let name = serde_json::to_string(&KeyboardActionKey::from(key)).unwrap();
+            if let Some(other) = names.insert(name.clone(), key) {
+                // Left and right keys share a name and are told apart by their side. Hanja and
+                // Hanji are the same virtual key.
+                let sided = KeySide::of(key).is_some()
+                    && KeySide::of(other).is_some()
+                    && KeySide::of(key) != KeySide::of(other);
+                let hanja = matches!((other, key), (Key::Hanja, Key::Hanji));
+                assert!(
+                    sided || hanja,
+                    "{:?} and {:?} are both sent as {}",
+                    other,
+                    key,
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_rdev_keys_keep_their_code() {
+        assert_eq!(
+            KeyboardActionKey::from(Key::Unknown(42)),
+            KeyboardActionKey::Unknown(42)
+        );
+    }
+}
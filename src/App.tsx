@@ -14,6 +14,75 @@ interface Alert {
   message: string;
 }
 
+interface UploadProgress {
+  session_id: string;
+  chunk: {
+    file_name: string;
+    state: 'pending' | 'uploading' | 'done' | 'failed';
+    attempts: number;
+    last_error: string | null;
+  };
+  done: number;
+  failed: number;
+  total: number;
+}
+
+interface CurrentUser {
+  id: string;
+  email: string | null;
+  expires_at: number;
+}
+
+type ApiProfile = 'prod' | 'staging' | 'local';
+interface ApiSettings {
+  profile: ApiProfile;
+  base_url: string;
+  from_env: boolean;
+}
+
+type RecorderErrorKind =
+  | 'ffmpeg_missing'
+  | 'ffmpeg_exited'
+  | 'permission_denied'
+  | 'disk_full'
+  | 'input_hook_failed'
+  | 'network'
+  | 'not_logged_in'
+  | 'invalid_config'
+  | 'invalid_state'
+  | 'unsupported'
+  | 'other';
+interface RecorderError {
+  kind: RecorderErrorKind;
+  message?: string;
+}
+
+const describeError = (e: RecorderError) => {
+  switch (e.kind) {
+    case 'ffmpeg_missing': return `FFmpeg is not available: ${e.message}`;
+    case 'ffmpeg_exited': return `FFmpeg stopped: ${e.message}`;
+    case 'permission_denied': return `Permission to record was denied, check the screen recording and input monitoring settings: ${e.message}`;
+    case 'disk_full': return 'The disk is full, free some space to keep recording';
+    case 'input_hook_failed': return `Failed to capture input events: ${e.message}`;
+    case 'network': return `Network error: ${e.message}`;
+    case 'not_logged_in': return 'Not logged in, log in before recording';
+    case 'invalid_config': return `Invalid capture settings: ${e.message}`;
+    case 'unsupported': return `Not supported on this platform: ${e.message}`;
+    default: return e.message ?? e.kind;
+  }
+};
+
+type RecorderState = 'idle' | 'starting' | 'recording' | 'paused' | 'stopping' | 'uploading' | 'error';
+interface RecorderStatus {
+  state: RecorderState;
+  session_id: string | null;
+  elapsed_ms: number;
+  event_count: number;
+  chunk_count: number;
+  pending_uploads: number;
+  error: RecorderError | null;
+}
+
 interface RecordingAnalysis {
   total_duration: number;
   total_events: number;
@@ -22,9 +91,15 @@ interface RecordingAnalysis {
 }
 
 export default function MainScreen() {
-  const [isRecording, setIsRecording] = useState<boolean>(false);
+  const [status, setStatus] = useState<RecorderStatus | null>(null);
   const [alert, setAlert] = useState<Alert | null>(null);
   const [analysis, setAnalysis] = useState<RecordingAnalysis | null>(null);
+  const [uploadProgress, setUploadProgress] = useState<UploadProgress | null>(null);
+  const [user, setUser] = useState<CurrentUser | null>(null);
+  const [apiSettings, setApiSettings] = useState<ApiSettings | null>(null);
+
+  const isRecording = ['starting', 'recording', 'paused', 'stopping'].includes(status?.state ?? 'idle');
+  const isPaused = status?.state === 'paused';
 
   useEffect(() => {
     // Set theme based on system preference
@@ -40,35 +115,78 @@ export default function MainScreen() {
     }
     setupLogs();
 
+    invoke<CurrentUser | null>('current_user').then(setUser);
+    invoke<ApiSettings>('get_api_settings').then(setApiSettings);
+    invoke<RecorderStatus>('get_recorder_status').then(setStatus);
+
+    const unlistenState = listen<RecorderStatus>('recorder_state_changed', (event) => {
+      setStatus(event.payload);
+    });
+
     const unlistenComplete = listen('recording_complete', (event) => {
       info(`Recording completed: ${event.payload}`);
       setAlert({ type: 'success', message: `Recording completed successfully. Saved to ${event.payload}` });
-      setIsRecording(false);
       analyzeRecording();
     });
 
-    const unlistenError = listen('recording_error', (event) => {
-      error(`Recording error: ${event.payload}`);
-      setAlert({ type: 'error', message: `Recording failed: ${event.payload}` });
-      setIsRecording(false);
+    const unlistenError = listen<RecorderError>('recorder_error', (event) => {
+      error(`Recording error: ${event.payload.kind}: ${event.payload.message}`);
+      setAlert({ type: 'error', message: `Recording failed: ${describeError(event.payload)}` });
+    });
+
+    const unlistenUpload = listen<UploadProgress>('upload_progress', (event) => {
+      if (event.payload.chunk.state === 'failed') {
+        error(`Upload of ${event.payload.chunk.file_name} failed: ${event.payload.chunk.last_error}`);
+      }
+      setUploadProgress(event.payload);
+    });
+
+    const unlistenAuth = listen('auth_required', () => {
+      setAlert({ type: 'error', message: 'Your session expired, please log in again to keep uploading.' });
+    });
+
+    const unlistenAuthChanged = listen<CurrentUser | null>('auth_changed', (event) => {
+      setUser(event.payload);
     });
 
     return () => {
+      unlistenAuth.then(f => f());
+      unlistenState.then(f => f());
+      unlistenAuthChanged.then(f => f());
       unlistenComplete.then(f => f());
       unlistenError.then(f => f());
+      unlistenUpload.then(f => f());
     };
   }, []);
 
+  // Phase changes are pushed, the counters are polled while there is something to count
+  useEffect(() => {
+    if (!isRecording && status?.state !== 'uploading') {
+      return;
+    }
+    const interval = setInterval(() => {
+      invoke<RecorderStatus>('get_recorder_status').then(setStatus);
+    }, 1000);
+    return () => clearInterval(interval);
+  }, [isRecording, status?.state]);
+
   const startRecording = async () => {
     try {
-      setIsRecording(true);
       setAlert(null);
       setAnalysis(null);
       await invoke('start_recording');
-    } catch (error: any) {
-      error(`Failed to start recording: ${error}`);
-      setAlert({ type: 'error', message: 'Failed to start recording' });
-      setIsRecording(false);
+    } catch (e: any) {
+      error(`Failed to start recording: ${e.kind}: ${e.message}`);
+      setAlert({ type: 'error', message: `Failed to start recording: ${describeError(e)}` });
+    }
+  };
+
+  const togglePause = async () => {
+    try {
+      await invoke(isPaused ? 'resume_recording' : 'pause_recording');
+    } catch (e: any) {
+      error(`Failed to ${isPaused ? 'resume' : 'pause'} recording: ${e.message}`);
+      setAlert({ type: 'error', message: describeError(e) });
     }
   };
 
@@ -76,10 +194,24 @@ export default function MainScreen() {
     try {
       await invoke('stop_recording');
       // The recording_complete event will handle the rest
-    } catch (error: any) {
-      error(`Failed to stop recording: ${error}`);
-      setAlert({ type: 'error', message: 'Failed to stop recording' });
-      setIsRecording(false);
+    } catch (e: any) {
+      error(`Failed to stop recording: ${e.message}`);
+      setAlert({ type: 'error', message: `Failed to stop recording: ${describeError(e)}` });
+    }
+  };
+
+  const changeApiProfile = async (profile: ApiProfile) => {
+    // Staging has no built-in URL
+    let baseUrl: string | null = null;
+    if (profile === 'staging') {
+      baseUrl = window.prompt('Base URL of the staging API');
+      if (!baseUrl) return;
+    }
+    try {
+      setApiSettings(await invoke<ApiSettings>('set_api_profile', { profile, baseUrl }));
+    } catch (e: any) {
+      error(`Failed to switch API profile: ${e}`);
+      setAlert({ type: 'error', message: `Failed to switch API profile: ${e}` });
     }
   };
 
@@ -107,6 +239,33 @@ export default function MainScreen() {
           </Alert>
         )}
 
+        <Card className="w-full mb-8 bg-white dark:bg-gray-800">
+          <CardContent className="flex items-center justify-between pt-6">
+            <div className="text-left text-sm">
+              <p>{user ? `Logged in as ${user.email ?? user.id}` : 'Not logged in'}</p>
+              {apiSettings && (
+                <p className="text-gray-500 dark:text-gray-400">
+                  <select
+                    value={apiSettings.profile}
+                    disabled={apiSettings.from_env || isRecording}
+                    onChange={(e) => changeApiProfile(e.target.value as ApiProfile)}
+                    className="mr-2 bg-transparent"
+                  >
+                    <option value="prod">prod</option>
+                    <option value="staging">staging</option>
+                    <option value="local">local</option>
+                  </select>
+                  {apiSettings.base_url}
+                  {apiSettings.from_env && ' (set by environment)'}
+                </p>
+              )}
+            </div>
+            <Button variant="outline" onClick={() => invoke(user ? 'logout' : 'login')}>
+              {user ? 'Log out' : 'Log in'}
+            </Button>
+          </CardContent>
+        </Card>
+
         <Card className="w-full mb-8 bg-white dark:bg-gray-800">
           <CardHeader>
             <CardTitle>Recording Controls</CardTitle>
@@ -120,13 +279,20 @@ export default function MainScreen() {
             >
               {isRecording ? (
                 <>
-                  <Loader2 className="mr-2 h-4 w-4 animate-spin" />
-                  Recording...
+                  {!isPaused && <Loader2 className="mr-2 h-4 w-4 animate-spin" />}
+                  {isPaused ? 'Paused' : 'Recording...'}
                 </>
               ) : (
                 'Start Recording'
               )}
             </Button>
+            <Button
+              onClick={togglePause}
+              disabled={!isRecording}
+              variant="outline"
+            >
+              {isPaused ? 'Resume' : 'Pause'}
+            </Button>
             <Button
               onClick={stopRecording}
               disabled={!isRecording}
@@ -136,6 +302,19 @@ export default function MainScreen() {
               Stop Recording
             </Button>
           </CardContent>
+          {status && status.state !== 'idle' && (
+            <p className="pb-2 text-sm text-gray-500 dark:text-gray-400">
+              {status.state} · {Math.floor(status.elapsed_ms / 1000)}s · {status.event_count} events
+              · {status.chunk_count} chunks · {status.pending_uploads} pending uploads
+              {status.error && ` · ${describeError(status.error)}`}
+            </p>
+          )}
+          {uploadProgress && (
+            <p className="pb-4 text-sm text-gray-500 dark:text-gray-400">
+              Uploaded {uploadProgress.done} of {uploadProgress.total} chunks
+              {uploadProgress.failed > 0 && `, ${uploadProgress.failed} failed`}
+            </p>
+          )}
         </Card>
 
         {analysis && (